@@ -0,0 +1,322 @@
+//! `compare` subcommand: diffs the measured phase of two saved run results
+//! and fails when the candidate regressed past the thresholds.
+
+use crate::report::{PhaseReport, Report};
+use clap::Args;
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Result file of the reference run (JSON, as saved by every run)
+    baseline: PathBuf,
+
+    /// Result file of the run to check
+    candidate: PathBuf,
+
+    /// Allowed throughput drop, in percent
+    #[arg(long, default_value_t = 5.0)]
+    throughput_threshold: f64,
+
+    /// Allowed increase of mean and percentile latencies, in percent
+    #[arg(long, default_value_t = 10.0)]
+    latency_threshold: f64,
+
+    /// Allowed increase of the error rate, in percentage points
+    #[arg(long, default_value_t = 0.1)]
+    error_rate_threshold: f64,
+}
+
+/// Which direction of change is a regression.
+#[derive(Clone, Copy)]
+enum Better {
+    Higher,
+    Lower,
+}
+
+struct Metric {
+    name: &'static str,
+    unit: &'static str,
+    baseline: f64,
+    candidate: f64,
+    better: Better,
+    /// Allowed change before it counts as a regression
+    threshold: f64,
+    /// Whether `threshold` is relative (percent) or absolute (same unit as values)
+    relative: bool,
+}
+
+impl Metric {
+    /// Change from baseline to candidate: percent for relative metrics,
+    /// absolute difference otherwise. `None` when the baseline is zero.
+    fn delta(&self) -> Option<f64> {
+        if !self.relative {
+            Some(self.candidate - self.baseline)
+        } else if self.baseline == 0.0 {
+            None
+        } else {
+            Some((self.candidate - self.baseline) / self.baseline * 100.0)
+        }
+    }
+
+    fn regressed(&self) -> bool {
+        let Some(delta) = self.delta() else {
+            return false;
+        };
+        match self.better {
+            Better::Higher => -delta > self.threshold,
+            Better::Lower => delta > self.threshold,
+        }
+    }
+
+    fn improved(&self) -> bool {
+        match (self.delta(), self.better) {
+            (Some(delta), Better::Higher) => delta > 0.0,
+            (Some(delta), Better::Lower) => delta < 0.0,
+            (None, _) => false,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        if self.regressed() {
+            "REGRESSION"
+        } else if self.improved() {
+            "improved"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// Prints the comparison and returns whether any metric regressed.
+pub fn run(args: &CompareArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    let baseline = load(&args.baseline)?;
+    let candidate = load(&args.candidate)?;
+
+    println!("=== Comparison ===");
+    println!("Baseline:  {} ({}, {})", args.baseline.display(), baseline.config.server, baseline.started_at);
+    println!("Candidate: {} ({}, {})", args.candidate.display(), candidate.config.server, candidate.started_at);
+
+    let (b, c) = (&baseline.config, &candidate.config);
+    if b.rpc != c.rpc
+        || b.clients != c.clients
+        || b.connections != c.connections
+        || b.rate != c.rate
+        || b.messages != c.messages
+    {
+        println!(
+            "Note: workloads differ (baseline {} x{} clients over {} connections, candidate {} x{} clients over {} connections); deltas may not be meaningful",
+            b.rpc, b.clients, b.connections, c.rpc, c.clients, c.connections
+        );
+    }
+
+    let metrics = metrics(args, measured(&baseline)?, measured(&candidate)?);
+    println!(
+        "\n{:<12} {:>14} {:>14} {:>10}  Status",
+        "Metric", "Baseline", "Candidate", "Delta"
+    );
+    let mut regressions = 0;
+    for m in &metrics {
+        let delta = match m.delta() {
+            Some(d) if m.relative => format!("{:+.1}%", d),
+            Some(d) => format!("{:+.2}{}", d, m.unit),
+            None => "n/a".to_string(),
+        };
+        if m.regressed() {
+            regressions += 1;
+        }
+        println!(
+            "{:<12} {:>14} {:>14} {:>10}  {}",
+            m.name,
+            format!("{:.2}{}", m.baseline, m.unit),
+            format!("{:.2}{}", m.candidate, m.unit),
+            delta,
+            m.status()
+        );
+    }
+
+    if regressions == 0 {
+        println!("\nNo regressions");
+    } else {
+        println!("\n{} regression(s) beyond thresholds", regressions);
+    }
+    Ok(regressions > 0)
+}
+
+fn load(path: &Path) -> Result<Report, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let report = serde_json::from_str(&text).map_err(|e| format!("{} is not a benchmark result: {}", path.display(), e))?;
+    Ok(report)
+}
+
+fn measured(report: &Report) -> Result<&PhaseReport, String> {
+    report
+        .phases
+        .iter()
+        .find(|p| p.phase == "measure")
+        .ok_or_else(|| "result has no measure phase".to_string())
+}
+
+fn metrics(args: &CompareArgs, b: &PhaseReport, c: &PhaseReport) -> Vec<Metric> {
+    let error_rate = |p: &PhaseReport| {
+        if p.requests == 0 {
+            0.0
+        } else {
+            p.errors as f64 / p.requests as f64 * 100.0
+        }
+    };
+    let ms = |us: f64| us / 1000.0;
+    let latency = |name, baseline: f64, candidate: f64| Metric {
+        name,
+        unit: "ms",
+        baseline: ms(baseline),
+        candidate: ms(candidate),
+        better: Better::Lower,
+        threshold: args.latency_threshold,
+        relative: true,
+    };
+
+    vec![
+        Metric {
+            name: "throughput",
+            unit: "/s",
+            baseline: b.throughput,
+            candidate: c.throughput,
+            better: Better::Higher,
+            threshold: args.throughput_threshold,
+            relative: true,
+        },
+        latency("mean", b.latency.mean_us, c.latency.mean_us),
+        latency("p50", b.latency.p50_us as f64, c.latency.p50_us as f64),
+        latency("p90", b.latency.p90_us as f64, c.latency.p90_us as f64),
+        latency("p99", b.latency.p99_us as f64, c.latency.p99_us as f64),
+        latency("p99.9", b.latency.p999_us as f64, c.latency.p999_us as f64),
+        Metric {
+            name: "error rate",
+            unit: "%",
+            baseline: error_rate(b),
+            candidate: error_rate(c),
+            better: Better::Lower,
+            threshold: args.error_rate_threshold,
+            relative: false,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> CompareArgs {
+        CompareArgs {
+            baseline: PathBuf::from("baseline.json"),
+            candidate: PathBuf::from("candidate.json"),
+            throughput_threshold: 5.0,
+            latency_threshold: 10.0,
+            error_rate_threshold: 0.1,
+        }
+    }
+
+    /// A measure phase as an older benchmark saved it, without the optional sections.
+    fn phase(requests: usize, errors: usize, throughput: f64, p99_us: u64) -> PhaseReport {
+        serde_json::from_value(serde_json::json!({
+            "phase": "measure",
+            "duration_secs": 10.0,
+            "requests": requests,
+            "errors": errors,
+            "throughput": throughput,
+            "latency": {
+                "count": requests, "min_us": 100, "mean_us": 1000.0, "p50_us": 900,
+                "p90_us": 1500, "p99_us": p99_us, "p999_us": 5000, "max_us": 8000,
+            },
+            "send_lag": null,
+            "errors_by_code": {},
+        }))
+        .unwrap()
+    }
+
+    fn statuses(baseline: &PhaseReport, candidate: &PhaseReport) -> Vec<(&'static str, &'static str)> {
+        metrics(&args(), baseline, candidate)
+            .iter()
+            .map(|m| (m.name, m.status()))
+            .collect()
+    }
+
+    fn status_of(name: &str, baseline: &PhaseReport, candidate: &PhaseReport) -> &'static str {
+        statuses(baseline, candidate)
+            .into_iter()
+            .find(|(metric, _)| *metric == name)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn identical_runs_have_no_regressions() {
+        let run = phase(10_000, 0, 1000.0, 2000);
+        assert!(statuses(&run, &run).iter().all(|(_, status)| *status == "ok"));
+    }
+
+    #[test]
+    fn improvements_are_never_regressions() {
+        let baseline = phase(10_000, 50, 1000.0, 2000);
+        let candidate = phase(10_000, 0, 1500.0, 1000);
+        assert_eq!(status_of("throughput", &baseline, &candidate), "improved");
+        assert_eq!(status_of("p99", &baseline, &candidate), "improved");
+        assert_eq!(status_of("error rate", &baseline, &candidate), "improved");
+    }
+
+    #[test]
+    fn changes_within_the_thresholds_pass() {
+        let baseline = phase(10_000, 0, 1000.0, 2000);
+        // 4% slower, 9% higher p99 and 0.05 points more errors
+        let candidate = phase(10_000, 5, 960.0, 2180);
+        assert_eq!(status_of("throughput", &baseline, &candidate), "ok");
+        assert_eq!(status_of("p99", &baseline, &candidate), "ok");
+        assert_eq!(status_of("error rate", &baseline, &candidate), "ok");
+    }
+
+    #[test]
+    fn changes_beyond_the_thresholds_regress() {
+        let baseline = phase(10_000, 0, 1000.0, 2000);
+        // 6% slower, 11% higher p99 and 0.2 points more errors
+        let candidate = phase(10_000, 20, 940.0, 2220);
+        assert_eq!(status_of("throughput", &baseline, &candidate), "REGRESSION");
+        assert_eq!(status_of("p99", &baseline, &candidate), "REGRESSION");
+        assert_eq!(status_of("error rate", &baseline, &candidate), "REGRESSION");
+        assert_eq!(status_of("p50", &baseline, &candidate), "ok");
+    }
+
+    #[test]
+    fn metrics_the_baseline_lacks_cannot_regress() {
+        // A baseline with no requests has zero throughput and latencies
+        let baseline = phase(0, 0, 0.0, 0);
+        let candidate = phase(10_000, 0, 1000.0, 2000);
+        let compared = metrics(&args(), &baseline, &candidate);
+        let throughput = compared.iter().find(|m| m.name == "throughput").unwrap();
+        assert_eq!(throughput.delta(), None);
+        assert_eq!(status_of("throughput", &baseline, &candidate), "ok");
+        assert_eq!(status_of("p99", &baseline, &candidate), "ok");
+        // Error rates compare in points, and no requests counts as no errors
+        assert_eq!(status_of("error rate", &baseline, &candidate), "ok");
+    }
+
+    #[test]
+    fn a_result_without_a_measure_phase_is_rejected() {
+        let mut report: Report = serde_json::from_value(serde_json::json!({
+            "config": {
+                "server": "http://[::1]:50051", "rpc": "unary", "clients": 1,
+                "requests": null, "duration_secs": 1.0, "warmup_secs": 1.0, "ramp_up_secs": 0.0,
+                "messages": 1, "rate": null, "tls": false,
+            },
+            "started_at": "2026-01-01T00:00:00Z",
+            "finished_at": "2026-01-01T00:00:02Z",
+            "total_duration_secs": 2.0,
+            "phases": [],
+            "throughput": [],
+        }))
+        .unwrap();
+        let mut warmup = phase(100, 0, 100.0, 2000);
+        warmup.phase = "warmup".to_string();
+        report.phases.push(warmup);
+        assert!(measured(&report).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+//! `--dashboard`: a live view of a run, redrawn every second on the
+//! terminal's alternate screen, so saturation shows while it happens.
+//!
+//! Clients report into a shared [`LiveStats`]; the render task takes a
+//! snapshot each tick. Log lines are captured by [`LogBuffer`] instead of
+//! being printed over the screen.
+
+use crate::phase::{Phase, RunPlan};
+use crate::stats::LatencyHistogram;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Seconds of latency the percentiles cover.
+const LATENCY_WINDOW: usize = 10;
+/// Connections listed individually; the rest are summarised in one line.
+const CONNECTION_ROWS: usize = 12;
+const LOG_LINES: usize = 6;
+
+const ENTER_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+const LEAVE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+#[derive(Debug)]
+struct ConnectionStats {
+    connected: bool,
+    sent: AtomicU64,
+    finished: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Counters every client updates as calls are sent and finish.
+#[derive(Debug)]
+pub struct LiveStats {
+    sent: AtomicU64,
+    finished: AtomicU64,
+    measured: AtomicU64,
+    errors: AtomicU64,
+    /// Latencies of the calls finished since the last tick
+    latencies: Mutex<LatencyHistogram>,
+    errors_by_code: Mutex<BTreeMap<String, u64>>,
+    connections: Vec<ConnectionStats>,
+}
+
+impl LiveStats {
+    /// `connected` says, per connection, whether it opened.
+    pub fn new(connected: impl IntoIterator<Item = bool>) -> Arc<Self> {
+        Arc::new(Self {
+            sent: AtomicU64::new(0),
+            finished: AtomicU64::new(0),
+            measured: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latencies: Mutex::new(LatencyHistogram::new()),
+            errors_by_code: Mutex::new(BTreeMap::new()),
+            connections: connected
+                .into_iter()
+                .map(|connected| ConnectionStats {
+                    connected,
+                    sent: AtomicU64::new(0),
+                    finished: AtomicU64::new(0),
+                    errors: AtomicU64::new(0),
+                })
+                .collect(),
+        })
+    }
+
+    /// `client` is a client id, or a connection index for replays.
+    pub fn sent(&self, client: usize) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.connection(client).sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finished(&self, client: usize, phase: Phase, outcome: Result<Duration, &tonic::Status>) {
+        let connection = self.connection(client);
+        self.finished.fetch_add(1, Ordering::Relaxed);
+        connection.finished.fetch_add(1, Ordering::Relaxed);
+        if phase == Phase::Measure {
+            self.measured.fetch_add(1, Ordering::Relaxed);
+        }
+        match outcome {
+            Ok(latency) => self.latencies.lock().unwrap().record(latency),
+            Err(status) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                connection.errors.fetch_add(1, Ordering::Relaxed);
+                *self
+                    .errors_by_code
+                    .lock()
+                    .unwrap()
+                    .entry(format!("{:?}", status.code()))
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn connection(&self, client: usize) -> &ConnectionStats {
+        &self.connections[client % self.connections.len()]
+    }
+}
+
+/// What the header shows about the run.
+pub struct RunInfo {
+    pub title: String,
+    pub plan: RunPlan,
+    /// Calls the measured phase will make, when the run is count-based
+    pub measured_calls: Option<u64>,
+    /// Planned length of the whole run, when known up front
+    pub planned: Option<Duration>,
+}
+
+/// A running dashboard; [`Dashboard::stop`] restores the terminal.
+pub struct Dashboard {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Dashboard {
+    /// Switches to the alternate screen and redraws every second until stopped.
+    pub fn start(live: Arc<LiveStats>, info: RunInfo, logs: LogBuffer) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            print!("{}", ENTER_SCREEN);
+            let mut screen = Screen::new(live, info, logs);
+            let mut ticks = tokio::time::interval(Duration::from_secs(1));
+            ticks.tick().await;
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => screen.draw(),
+                    _ = &mut stopped => break,
+                    _ = tokio::signal::ctrl_c() => {
+                        print!("{}", LEAVE_SCREEN);
+                        let _ = io::stdout().flush();
+                        std::process::exit(130);
+                    }
+                }
+            }
+            print!("{}", LEAVE_SCREEN);
+            let _ = io::stdout().flush();
+        });
+        Self { stop, task }
+    }
+
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+struct Screen {
+    live: Arc<LiveStats>,
+    info: RunInfo,
+    logs: LogBuffer,
+    last_tick: Instant,
+    last_finished: u64,
+    last_errors: u64,
+    last_by_code: BTreeMap<String, u64>,
+    last_connection_errors: Vec<u64>,
+    /// One histogram per recent second, newest last
+    window: VecDeque<LatencyHistogram>,
+}
+
+impl Screen {
+    fn new(live: Arc<LiveStats>, info: RunInfo, logs: LogBuffer) -> Self {
+        Self {
+            last_connection_errors: vec![0; live.connections.len()],
+            live,
+            last_tick: info.plan.start,
+            info,
+            logs,
+            last_finished: 0,
+            last_errors: 0,
+            last_by_code: BTreeMap::new(),
+            window: VecDeque::new(),
+        }
+    }
+
+    fn draw(&mut self) {
+        let frame = self.frame();
+        print!("{}{}", CLEAR, frame);
+        let _ = io::stdout().flush();
+    }
+
+    fn frame(&mut self) -> String {
+        let live = &self.live;
+        let now = Instant::now();
+        let tick = now.duration_since(self.last_tick).as_secs_f64().max(f64::EPSILON);
+        self.last_tick = now;
+
+        let finished = live.finished.load(Ordering::Relaxed);
+        let errors = live.errors.load(Ordering::Relaxed);
+        let sent = live.sent.load(Ordering::Relaxed);
+        let new_finished = finished - self.last_finished;
+        let new_errors = errors - self.last_errors;
+        self.last_finished = finished;
+        self.last_errors = errors;
+
+        let second = std::mem::take(&mut *live.latencies.lock().unwrap());
+        self.window.push_back(second);
+        if self.window.len() > LATENCY_WINDOW {
+            self.window.pop_front();
+        }
+        let mut latencies = LatencyHistogram::new();
+        for second in &self.window {
+            latencies.merge(second);
+        }
+        let latency = latencies.summary(Duration::ZERO);
+
+        let mut out = String::new();
+        let elapsed = now.duration_since(self.info.plan.start);
+        let _ = writeln!(out, "{}", self.info.title);
+        let _ = write!(out, "Phase: {:<8} Elapsed: {:.0?}", self.info.plan.phase_at(now).name(), elapsed);
+        if let Some(planned) = self.info.planned {
+            let _ = write!(out, " / {:.0?}", planned);
+        }
+        if let Some(calls) = self.info.measured_calls {
+            let _ = write!(out, "   Measured calls: {} / {}", live.measured.load(Ordering::Relaxed), calls);
+        }
+        let _ = writeln!(out, "\n");
+
+        let _ = writeln!(out, "Throughput  {:>10.1} req/s   total {}", new_finished as f64 / tick, finished);
+        let _ = writeln!(out, "In flight   {:>10}", sent.saturating_sub(finished));
+        let _ = writeln!(
+            out,
+            "Errors      {:>9.2}%        total {} ({:.2}%)",
+            percent(new_errors, new_finished),
+            errors,
+            percent(errors, finished)
+        );
+        let _ = writeln!(
+            out,
+            "Latency (last {}s)  p50 {:?}  p90 {:?}  p99 {:?}  p99.9 {:?}  max {:?}",
+            self.window.len(),
+            latency.p50,
+            latency.p90,
+            latency.p99,
+            latency.p999,
+            latency.max
+        );
+
+        let by_code = live.errors_by_code.lock().unwrap().clone();
+        if !by_code.is_empty() {
+            let _ = writeln!(out, "\n{:<22} {:>10} {:>10} {:>8}", "Errors by code", "total", "last 1s", "share");
+            for (code, &count) in &by_code {
+                let before = self.last_by_code.get(code).copied().unwrap_or(0);
+                let _ = writeln!(
+                    out,
+                    "  {:<20} {:>10} {:>10} {:>7.1}%",
+                    code,
+                    count,
+                    count - before,
+                    percent(count, errors)
+                );
+            }
+        }
+        self.last_by_code = by_code;
+
+        let connections = &live.connections;
+        let failed = connections.iter().filter(|c| !c.connected).count();
+        let _ = writeln!(out, "\nConnections: {} open, {} failed", connections.len() - failed, failed);
+        let _ = writeln!(
+            out,
+            "  {:>4}  {:<9} {:>9} {:>10} {:>8}",
+            "#", "state", "in flight", "calls", "errors"
+        );
+        for (index, c) in connections.iter().enumerate() {
+            let errors = c.errors.load(Ordering::Relaxed);
+            let erroring = errors > self.last_connection_errors[index];
+            self.last_connection_errors[index] = errors;
+            if index >= CONNECTION_ROWS {
+                continue;
+            }
+            // "erroring" marks connections whose calls failed in the last second
+            let state = match (c.connected, erroring) {
+                (false, _) => "failed",
+                (true, true) => "erroring",
+                (true, false) => "ready",
+            };
+            let finished = c.finished.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "  {:>4}  {:<9} {:>9} {:>10} {:>8}",
+                index,
+                state,
+                c.sent.load(Ordering::Relaxed).saturating_sub(finished),
+                finished,
+                errors
+            );
+        }
+        if connections.len() > CONNECTION_ROWS {
+            let _ = writeln!(out, "  ... and {} more", connections.len() - CONNECTION_ROWS);
+        }
+
+        let logs = self.logs.recent(LOG_LINES);
+        if !logs.is_empty() {
+            let _ = writeln!(out, "\nRecent log");
+            for line in logs {
+                let _ = writeln!(out, "  {}", line);
+            }
+        }
+        let _ = writeln!(out, "\nCtrl-C aborts the run");
+        out
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+/// Keeps the latest log lines for the dashboard instead of writing them to
+/// the terminal it is drawing on.
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogBuffer {
+    fn recent(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+pub struct LogWriter {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    line: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.line);
+        let mut lines = self.lines.lock().unwrap();
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogWriter {
+            lines: self.lines.clone(),
+            line: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Failed calls bucketed by where they failed and their gRPC status code,
+//! so thousands of failures read as a handful of causes.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+/// Distinct example messages kept per bucket.
+const MAX_EXAMPLES: usize = 3;
+
+/// Where a call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The client's connection could not be opened, so no call was sent.
+    Connect,
+    /// The call broke on the client side: connection reset, HTTP/2 error, timeout.
+    Transport,
+    /// The server answered with a non-OK status.
+    Status,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Connect => "connect",
+            ErrorKind::Transport => "transport",
+            ErrorKind::Status => "status",
+        }
+    }
+
+    /// Statuses tonic builds from a local error keep it as their source;
+    /// statuses decoded from the server's trailers have none.
+    pub fn of(status: &Status) -> Self {
+        if status.source().is_some() {
+            ErrorKind::Transport
+        } else {
+            ErrorKind::Status
+        }
+    }
+}
+
+/// Failures sharing one kind and code.
+#[derive(Debug, Clone)]
+pub struct ErrorBucket {
+    pub count: usize,
+    /// Earliest failure, measured from the start of the run.
+    pub first_seen: Duration,
+    pub examples: Vec<String>,
+}
+
+impl ErrorBucket {
+    fn add_example(&mut self, example: &str) {
+        if self.examples.len() < MAX_EXAMPLES && !self.examples.iter().any(|e| e == example) {
+            self.examples.push(example.to_string());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ErrorTally {
+    buckets: BTreeMap<(ErrorKind, i32), ErrorBucket>,
+}
+
+impl ErrorTally {
+    /// Records a failed call `at` after the start of the run.
+    pub fn record(&mut self, status: &Status, at: Duration) {
+        let example = match status.source() {
+            Some(source) => describe(source),
+            None if status.message().is_empty() => "(no message)".to_string(),
+            None => status.message().to_string(),
+        };
+        self.add(ErrorKind::of(status), status.code(), 1, at, &example);
+    }
+
+    /// Records `calls` calls that never ran because the connection failed.
+    pub fn record_connect(&mut self, error: &str, calls: usize) {
+        self.add(ErrorKind::Connect, Code::Unavailable, calls, Duration::ZERO, error);
+    }
+
+    fn add(&mut self, kind: ErrorKind, code: Code, count: usize, at: Duration, example: &str) {
+        let bucket = self.buckets.entry((kind, code as i32)).or_insert_with(|| ErrorBucket {
+            count: 0,
+            first_seen: at,
+            examples: Vec::new(),
+        });
+        bucket.count += count;
+        bucket.first_seen = bucket.first_seen.min(at);
+        bucket.add_example(example);
+    }
+
+    pub fn merge(&mut self, other: ErrorTally) {
+        for (key, theirs) in other.buckets {
+            match self.buckets.get_mut(&key) {
+                Some(ours) => {
+                    ours.count += theirs.count;
+                    ours.first_seen = ours.first_seen.min(theirs.first_seen);
+                    for example in &theirs.examples {
+                        ours.add_example(example);
+                    }
+                }
+                None => {
+                    self.buckets.insert(key, theirs);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Buckets with the most failures first.
+    pub fn by_count(&self) -> Vec<(ErrorKind, Code, &ErrorBucket)> {
+        let mut buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|(&(kind, code), bucket)| (kind, Code::from(code), bucket))
+            .collect();
+        buckets.sort_by_key(|b| std::cmp::Reverse(b.2.count));
+        buckets
+    }
+
+    /// Failures by status code name, whatever their kind.
+    pub fn by_code(&self) -> BTreeMap<String, usize> {
+        let mut codes = BTreeMap::new();
+        for (&(_, code), bucket) in &self.buckets {
+            *codes.entry(format!("{:?}", Code::from(code))).or_insert(0) += bucket.count;
+        }
+        codes
+    }
+}
+
+/// An error and its sources, outermost first, e.g.
+/// `transport error: tcp connect error: Connection refused (os error 111)`.
+pub fn describe(error: &dyn Error) -> String {
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_text = cause.to_string();
+        // hyper and tonic often repeat the inner message in the outer one
+        if !text.ends_with(&cause_text) {
+            text = format!("{}: {}", text, cause_text);
+        }
+        source = cause.source();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use std::io;
+    use std::sync::Arc;
+
+    fn transport(code: Code, message: &str) -> Status {
+        let mut status = Status::new(code, "transport error");
+        status.set_source(Arc::new(io::Error::new(io::ErrorKind::ConnectionReset, message.to_string())));
+        status
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn statuses_from_the_server_are_status_errors() {
+        assert_eq!(ErrorKind::of(&Status::unavailable("overloaded")), ErrorKind::Status);
+        assert_eq!(ErrorKind::of(&Status::new(Code::Internal, "")), ErrorKind::Status);
+    }
+
+    #[test]
+    fn statuses_built_from_local_errors_are_transport_errors() {
+        assert_eq!(ErrorKind::of(&transport(Code::Unavailable, "reset")), ErrorKind::Transport);
+        let status = Status::from_error(Box::new(io::Error::other("h2 protocol error")));
+        assert_eq!(ErrorKind::of(&status), ErrorKind::Transport);
+    }
+
+    #[test]
+    fn keeps_a_few_distinct_examples_per_bucket() {
+        let mut tally = ErrorTally::default();
+        for message in ["a", "b", "a", "c", "d", "e"] {
+            tally.record(&Status::unavailable(message), secs(1));
+        }
+        let buckets = tally.by_count();
+        assert_eq!(buckets.len(), 1);
+        let (kind, code, bucket) = &buckets[0];
+        assert_eq!((*kind, *code), (ErrorKind::Status, Code::Unavailable));
+        assert_eq!(bucket.count, 6);
+        assert_eq!(bucket.examples, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn examples_describe_the_source_or_say_there_is_no_message() {
+        let mut tally = ErrorTally::default();
+        tally.record(&Status::internal(""), secs(0));
+        tally.record(&transport(Code::Unknown, "connection reset by peer"), secs(0));
+        let examples: Vec<_> = tally.by_count().into_iter().map(|(_, _, b)| b.examples.clone()).collect();
+        assert!(examples.contains(&vec!["(no message)".to_string()]));
+        assert!(examples.contains(&vec!["connection reset by peer".to_string()]));
+    }
+
+    #[test]
+    fn buckets_by_kind_and_code_most_frequent_first() {
+        let mut tally = ErrorTally::default();
+        tally.record(&Status::unavailable("down"), secs(3));
+        tally.record(&transport(Code::Unavailable, "reset"), secs(2));
+        tally.record(&transport(Code::Unavailable, "reset"), secs(1));
+        tally.record_connect("connection refused", 4);
+        let order: Vec<_> = tally.by_count().into_iter().map(|(kind, code, b)| (kind, code, b.count)).collect();
+        assert_eq!(
+            order,
+            [
+                (ErrorKind::Connect, Code::Unavailable, 4),
+                (ErrorKind::Transport, Code::Unavailable, 2),
+                (ErrorKind::Status, Code::Unavailable, 1),
+            ]
+        );
+        assert_eq!(tally.by_code(), BTreeMap::from([("Unavailable".to_string(), 7)]));
+        assert_eq!(tally.by_count()[1].2.first_seen, secs(1));
+    }
+
+    #[test]
+    fn merging_keeps_the_earliest_failure_and_the_example_cap() {
+        let mut ours = ErrorTally::default();
+        ours.record(&Status::aborted("a"), secs(5));
+        ours.record(&Status::aborted("b"), secs(6));
+        let mut theirs = ErrorTally::default();
+        theirs.record(&Status::aborted("b"), secs(2));
+        theirs.record(&Status::aborted("c"), secs(3));
+        theirs.record(&Status::aborted("d"), secs(4));
+        theirs.record(&Status::not_found("x"), secs(9));
+        ours.merge(theirs);
+
+        let buckets = ours.by_count();
+        let (_, code, aborted) = &buckets[0];
+        assert_eq!(*code, Code::Aborted);
+        assert_eq!(aborted.count, 5);
+        assert_eq!(aborted.first_seen, secs(2));
+        assert_eq!(aborted.examples, ["a", "b", "c"]);
+        assert_eq!(buckets[1].1, Code::NotFound);
+        assert!(!ours.is_empty());
+        assert!(ErrorTally::default().is_empty());
+    }
+
+    /// An error with a message and an optional cause, like hyper's.
+    #[derive(Debug)]
+    struct Wrapped(&'static str, Option<Box<Wrapped>>);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.1.as_deref().map(|e| e as &(dyn Error + 'static))
+        }
+    }
+
+    #[test]
+    fn describes_the_sources_outermost_first() {
+        let refused = Wrapped("Connection refused (os error 111)", None);
+        let connect = Wrapped("tcp connect error", Some(Box::new(refused)));
+        let error = Wrapped("transport error", Some(Box::new(connect)));
+        assert_eq!(
+            describe(&error),
+            "transport error: tcp connect error: Connection refused (os error 111)"
+        );
+    }
+
+    #[test]
+    fn describes_a_repeated_cause_once() {
+        let reset = Wrapped("connection reset", None);
+        let error = Wrapped("h2 error: connection reset", Some(Box::new(reset)));
+        assert_eq!(describe(&error), "h2 error: connection reset");
+    }
+}
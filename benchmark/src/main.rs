@@ -1,141 +1,1113 @@
-use grpc_demo_proto::{greeter_service_client::GreeterServiceClient, HelloRequest};
-use clap::Parser;
-use std::time::{Duration, Instant};
-use tracing::{info, warn, error};
-use tonic::transport::Channel;
+mod compare;
+mod dashboard;
+mod errors;
+mod phase;
+mod replay;
+mod report;
+mod scenario;
+mod stats;
+mod workload;
+
+use grpc_demo_client::hedge::HedgeDelay;
+use grpc_demo_client::balance::EndpointStats;
+use grpc_demo_client::{BalanceArgs, BalancedChannel, Balancer, HedgeArgs, RetryArgs, RetryStats, TlsArgs};
+use grpc_demo_proto::{greeter_service_client::GreeterServiceClient, HelloRequest, HelloResponse};
+use clap::{Parser, Subcommand};
+use dashboard::{Dashboard, LiveStats, LogBuffer, RunInfo};
+use phase::{Phase, PhaseStats, RunPlan};
+use replay::Replay;
+use report::{EndpointReport, HedgeReport, OutputFormat, PhaseReport, Report, RunConfig, StageInfo};
+use scenario::Scenario;
+use stats::{OpenStream, StreamTimings, ThroughputSeries};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tracing::{info, error};
+use tonic::metadata::MetadataMap;
+use tracing_subscriber::EnvFilter;
+use workload::{Payload, Rpc, Workload};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Number of concurrent clients
-    #[arg(short, long, default_value_t = 10)]
+    #[arg(short, long, default_value_t = workload::DEFAULT_CLIENTS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     clients: usize,
 
+    /// HTTP/2 connections shared by the clients, assigned round-robin:
+    /// 1 shares one connection, the default gives each client its own
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    connections: Option<u64>,
+
     /// Total number of requests per client
-    #[arg(short, long, default_value_t = 100)]
+    #[arg(short, long, default_value_t = workload::DEFAULT_REQUESTS)]
     requests: usize,
 
-    /// Server address
+    /// Run the measured phase for this long instead of a fixed number of
+    /// requests (e.g. 30s, 500ms, 2m)
+    #[arg(short, long, value_parser = parse_duration, conflicts_with = "requests")]
+    duration: Option<Duration>,
+
+    /// Send load for this long before measuring; these results are reported
+    /// separately and excluded from the totals
+    #[arg(long, value_parser = parse_duration, default_value = "0s")]
+    warmup: Duration,
+
+    /// Start clients one by one, spread evenly over this long; calls sent
+    /// meanwhile are excluded from the totals
+    #[arg(long, value_parser = parse_duration, default_value = "0s")]
+    ramp_up: Duration,
+
+    /// Server address, or a comma-separated list to balance calls over;
+    /// the default target of scenario stages
     #[arg(short, long, default_value = "http://[::1]:50051")]
     server: String,
+
+    #[command(flatten)]
+    balance: BalanceArgs,
+
+    /// RPC to call for each request
+    #[arg(long, value_enum, default_value_t = Rpc::Unary)]
+    rpc: Rpc,
+
+    /// Messages sent per call for the streaming RPCs
+    #[arg(short, long, default_value_t = workload::DEFAULT_MESSAGES)]
+    messages: usize,
+
+    /// Open-loop mode: send this many calls per second in total, on a fixed
+    /// schedule that does not wait for responses
+    #[arg(long, value_parser = parse_rate)]
+    rate: Option<f64>,
+
+    /// Run the stages of this TOML scenario file one after another instead
+    /// of the workload given by the flags above
+    #[arg(long, conflicts_with_all = [
+        "clients", "connections", "requests", "duration", "warmup", "ramp_up", "rpc", "messages", "rate",
+    ])]
+    scenario: Option<PathBuf>,
+
+    /// Send the calls recorded in this JSONL file (see the server's
+    /// --record-traffic) at their recorded times instead of generating load;
+    /// --clients caps how many are in flight at once
+    #[arg(long, conflicts_with_all = [
+        "requests", "duration", "warmup", "ramp_up", "rpc", "messages", "rate", "scenario",
+    ])]
+    replay: Option<PathBuf>,
+
+    /// Replay this many times faster than recorded, e.g. 2 or 0.5
+    #[arg(long, value_parser = parse_speed, default_value_t = 1.0, requires = "replay")]
+    speed: f64,
+
+    /// Show live throughput, latency, errors and connections, redrawn every
+    /// second, while the benchmark runs; log lines appear in its last panel
+    #[arg(long)]
+    dashboard: bool,
+
+    /// Also write a machine-readable report in this format
+    #[arg(long, value_enum)]
+    output: Option<OutputFormat>,
+
+    /// Where to write the report [default: benchmark-report.json or .csv];
+    /// scenario stages insert their name before the extension
+    #[arg(long, requires = "output")]
+    output_file: Option<PathBuf>,
+
+    /// Directory each run's JSON result is saved to, for `compare`
+    #[arg(long, default_value = "benchmark-results")]
+    results_dir: PathBuf,
+
+    /// Do not save this run's result
+    #[arg(long)]
+    no_save: bool,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    retry: RetryArgs,
+
+    #[command(flatten)]
+    hedge: HedgeArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare two saved results; exits non-zero on regressions
+    Compare(compare::CompareArgs),
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("'{}' is not a positive number of requests per second", s)),
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("'{}' is not a positive speed factor", s)),
+    }
+}
+
+/// Parses `500ms`, `30s`, `2m` or a bare number of seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else if let Some(mins) = s.strip_suffix('m') {
+        (mins, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 => Duration::try_from_secs_f64(value * scale)
+            .map_err(|_| format!("'{}' is not a duration like 500ms, 30s or 2m", s)),
+        _ => Err(format!("'{}' is not a duration like 500ms, 30s or 2m", s)),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
     let args = Args::parse();
+    // Logs would scroll the dashboard away, so they are shown inside it instead
+    let logs = if args.dashboard && std::io::stdout().is_terminal() {
+        Some(LogBuffer::default())
+    } else {
+        if args.dashboard {
+            eprintln!("warning: --dashboard needs a terminal; printing results only");
+        }
+        None
+    };
+    match &logs {
+        Some(logs) => tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_ansi(false)
+            .with_writer(logs.clone())
+            .init(),
+        None => tracing_subscriber::fmt::init(),
+    }
 
-    info!("Debugging error patterns: {} clients making {} requests each", 
-          args.clients, args.requests);
-
-    let start_time = Instant::now();
-    let mut handles = Vec::new();
+    // Exit 1 on regressions and 2 on unreadable input, so CI can tell them apart
+    if let Some(Command::Compare(compare_args)) = &args.command {
+        match compare::run(compare_args) {
+            Ok(false) => return Ok(()),
+            Ok(true) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
 
-    for client_id in 0..args.clients {
-        let server_url = args.server.clone();
-        let requests = args.requests;
-        
-        let handle = tokio::spawn(async move {
-            benchmark_client(client_id, server_url, requests).await
+    if let Err(e) = args.balance.urls(&args.server) {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    }
+    if let Some(path) = &args.scenario {
+        let defaults = scenario::Defaults {
+            server: &args.server,
+            endpoints_file: args.balance.endpoints_file.as_deref(),
+            balance: args.balance.config(),
+            retry: &args.retry,
+            hedge: &args.hedge,
+        };
+        let scenario = Scenario::load(path, &defaults).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
         });
-        handles.push(handle);
+        return run_scenario(&args, scenario, logs.as_ref()).await;
+    }
+
+    if args.hedge.hedge_after.is_some() && args.replay.is_none() && !matches!(args.rpc, Rpc::Unary) {
+        eprintln!("error: --hedge-after only applies to --rpc unary");
+        std::process::exit(2);
+    }
+    let replay = args.replay.as_ref().map(|path| {
+        Replay::load(path, args.speed).map(Arc::new).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        })
+    });
+    let workload = Workload {
+        server: args.server.clone(),
+        endpoints_file: args.balance.endpoints_file.clone(),
+        balance: args.balance.config(),
+        rpc: args.rpc,
+        clients: args.clients,
+        connections: Workload::connections_for(args.clients, args.connections.map(|n| n as usize)),
+        requests: args.requests,
+        duration: args.duration,
+        warmup: args.warmup,
+        ramp_up: args.ramp_up,
+        messages: args.messages,
+        rate: args.rate,
+        payload: Payload::default(),
+        metadata: Default::default(),
+        retry: args.retry.policy(),
+        hedge: args.hedge.policy(),
+        replay,
+    };
+    let outcome = run(Arc::new(workload.clone()), &args.tls, logs.as_ref(), None).await?;
+    print_results(&workload, &outcome);
+    save(&args, &build_report(&workload, &args.tls, &outcome, None))?;
+    Ok(())
+}
+
+/// Runs every stage in order, reporting each one on its own, then prints a
+/// line per stage.
+async fn run_scenario(args: &Args, scenario: Scenario, logs: Option<&LogBuffer>) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Scenario '{}': {} stages", scenario.name, scenario.stages.len());
+    let mut summaries = Vec::new();
+    for (index, stage) in scenario.stages.iter().enumerate() {
+        let title = format!("Stage {}/{}: {}", index + 1, scenario.stages.len(), stage.name);
+        println!("\n##### {} #####", title);
+        stats::reset_peak_open_streams();
+        let outcome = run(Arc::new(stage.workload.clone()), &args.tls, logs, Some(&title)).await?;
+        print_results(&stage.workload, &outcome);
+
+        let info = StageInfo {
+            scenario: scenario.name.clone(),
+            index,
+            name: stage.name.clone(),
+        };
+        save(args, &build_report(&stage.workload, &args.tls, &outcome, Some(info)))?;
+
+        let measured = outcome.totals.phase(Phase::Measure);
+        let duration = outcome.plan.phase_duration(Phase::Measure, outcome.total_duration);
+        summaries.push((stage.name.as_str(), measured.requests, measured.errors, measured.latencies.summary(duration)));
+    }
+
+    println!("\n=== Scenario '{}' ===", scenario.name);
+    for (name, requests, errors, summary) in summaries {
+        println!(
+            "  {}: {} requests, {} errors, {:.1} req/s, p50 {:?}, p99 {:?}",
+            name,
+            requests,
+            errors,
+            summary.throughput(),
+            summary.p50,
+            summary.p99
+        );
+    }
+    Ok(())
+}
+
+/// Everything a finished run produced.
+struct RunOutcome {
+    started_at: SystemTime,
+    finished_at: SystemTime,
+    plan: RunPlan,
+    total_duration: Duration,
+    totals: BenchmarkResult,
+    /// Balanced runs only: calls per server, over the whole run
+    endpoints: Option<Vec<EndpointStats>>,
+}
+
+/// With `logs`, draws a dashboard while the run lasts; `stage` names a
+/// scenario stage in its title.
+async fn run(
+    workload: Arc<Workload>,
+    tls: &TlsArgs,
+    logs: Option<&LogBuffer>,
+    stage: Option<&str>,
+) -> Result<RunOutcome, tokio::task::JoinError> {
+    match (&workload.replay, workload.duration) {
+        (Some(replay), _) => info!("Replaying {} calls from {} at {}x speed over {:?}, at most {} in flight",
+                                   replay.calls.len(), replay.path.display(), replay.speed, replay.span(),
+                                   workload.clients),
+        (None, Some(duration)) => info!("Running {} clients making {:?} requests for {:?}",
+                                        workload.clients, workload.rpc, duration),
+        (None, None) => info!("Running {} clients making {} {:?} requests each",
+                              workload.clients, workload.requests, workload.rpc),
+    }
+    if !workload.ramp_up.is_zero() || !workload.warmup.is_zero() {
+        info!("Ramp-up {:?}, then warmup {:?} before measuring", workload.ramp_up, workload.warmup);
+    }
+    if let Some(rate) = workload.rate {
+        info!("Open loop: {} req/s in total, latency measured from scheduled send time", rate);
+    }
+    info!("{} clients multiplexed over {} connections", workload.clients, workload.connections);
+    if workload.retry.enabled() {
+        info!("Retrying {:?} up to {} attempts per call", workload.retry.retryable, workload.retry.max_attempts);
+    }
+    if let Some(hedge) = &workload.hedge {
+        info!("Hedging unary calls after {}, at most {} of them", hedge.after, hedge.max_fraction);
+    }
+
+    // Clients are pinned to connections round-robin, so client `c` uses connection `c % connections`
+    let (balancer, channels) = connect_all(&workload, tls).await;
+
+    let started_at = SystemTime::now();
+    let plan = RunPlan {
+        start: Instant::now(),
+        clients: workload.clients,
+        ramp_up: workload.ramp_up,
+        warmup: workload.warmup,
+        duration: workload.duration,
+        requests: workload.requests,
+    };
+
+    let live = logs.map(|_| LiveStats::new(channels.iter().map(|c| c.is_ok())));
+    let dashboard = logs.zip(live.clone()).map(|(logs, live)| {
+        let info = dashboard_info(&workload, plan, stage);
+        Dashboard::start(live, info, logs.clone())
+    });
+
+    let totals = match &workload.replay {
+        Some(replay) => replay_calls(replay.clone(), workload.clone(), channels, plan, workload.clients, live).await,
+        None => {
+            let mut handles = Vec::new();
+            for client_id in 0..workload.clients {
+                let channel = channels[client_id % workload.connections].clone();
+                let workload = workload.clone();
+                let live = live.clone();
+
+                let handle = tokio::spawn(async move {
+                    benchmark_client(client_id, channel, plan, workload, live).await
+                });
+                handles.push(handle);
+            }
+
+            let mut totals = BenchmarkResult::default();
+            for handle in handles {
+                totals.merge(handle.await?);
+            }
+            totals
+        }
+    };
+    if let Some(dashboard) = dashboard {
+        dashboard.stop().await;
     }
 
-    let mut total_requests = 0;
-    let mut total_errors = 0;
-    let mut error_details = Vec::new();
+    Ok(RunOutcome {
+        started_at,
+        finished_at: SystemTime::now(),
+        plan,
+        total_duration: plan.start.elapsed(),
+        totals,
+        endpoints: (balancer.urls().len() > 1 || workload.endpoints_file.is_some()).then(|| balancer.stats()),
+    })
+}
 
-    for handle in handles {
-        let result = handle.await?;
-        total_requests += result.requests;
-        total_errors += result.errors;
-        error_details.extend(result.error_details);
+fn dashboard_info(workload: &Workload, plan: RunPlan, stage: Option<&str>) -> RunInfo {
+    let mut title = format!(
+        "{} against {}: {} clients over {} connections",
+        workload.rpc_name(),
+        workload.target(),
+        workload.clients,
+        workload.connections
+    );
+    if let Some(stage) = stage {
+        title = format!("{} | {}", stage, title);
+    }
+    let (measured_calls, planned) = match (&workload.replay, workload.duration) {
+        (Some(replay), _) => (Some(replay.calls.len() as u64), Some(replay.span())),
+        (None, Some(duration)) => (None, Some(workload.ramp_up + workload.warmup + duration)),
+        (None, None) => (Some((workload.clients * workload.requests) as u64), None),
+    };
+    RunInfo {
+        title,
+        plan,
+        measured_calls,
+        planned,
     }
+}
 
-    let total_duration = start_time.elapsed();
-    let success_rate = (total_requests - total_errors) as f64 / total_requests as f64 * 100.0;
+fn print_results(workload: &Workload, outcome: &RunOutcome) {
+    let RunOutcome { plan, total_duration, totals, .. } = outcome;
+    let total_duration = *total_duration;
+    let measure_duration = plan.phase_duration(Phase::Measure, total_duration);
+    let measured = totals.phase(Phase::Measure);
+    let total_requests = measured.requests;
+    let total_errors = measured.errors;
 
     println!("\n=== Error Analysis Results ===");
     println!("Total requests: {}", total_requests);
     println!("Total errors: {}", total_errors);
-    println!("Success rate: {:.4}%", success_rate);
+    if total_requests == 0 {
+        // e.g. a duration that ended during warmup
+        println!("Success rate: n/a (no requests were measured)");
+    } else {
+        let success_rate = (total_requests - total_errors) as f64 / total_requests as f64 * 100.0;
+        println!("Success rate: {:.4}%", success_rate);
+    }
     println!("Total duration: {:?}", total_duration);
 
-    if !error_details.is_empty() {
-        println!("\n=== Error Breakdown ===");
-        let mut error_counts = std::collections::HashMap::new();
-        for error in &error_details {
-            *error_counts.entry(error.clone()).or_insert(0) += 1;
+    let streaming = workload.streams_replies();
+    if streaming {
+        println!("\n=== Latency (stream completion) ===");
+    } else {
+        println!("\n=== Latency ===");
+    }
+    println!("{}", measured.latencies.summary(measure_duration));
+
+    if workload.open_loop() {
+        // How late calls left compared to the timetable; a growing lag means
+        // the generator, not the server, became the bottleneck.
+        let lag = measured.send_lag.summary(measure_duration);
+        println!("\n=== Schedule (open loop) ===");
+        if let Some(rate) = workload.rate {
+            println!("Target rate: {:.1} req/s", rate);
+        }
+        if let Some(replay) = &workload.replay {
+            println!("Replayed: {} at {}x speed", replay.path.display(), replay.speed);
+        }
+        println!("Send lag p50: {:?}", lag.p50);
+        println!("Send lag p99: {:?}", lag.p99);
+        println!("Send lag max: {:?}", lag.max);
+    }
+
+    if streaming {
+        let streams = &measured.streams;
+        let first = streams.first_message.summary(measure_duration);
+        let gaps = streams.inter_arrival.summary(measure_duration);
+        println!("\n=== Streams ===");
+        println!("Streams completed: {}", streams.streams);
+        println!("Peak open streams: {}", stats::peak_open_streams());
+        println!(
+            "Messages received: {} ({:.1} msg/s)",
+            streams.messages,
+            streams.messages as f64 / measure_duration.as_secs_f64()
+        );
+        println!("First message p50: {:?}, p99: {:?}, max: {:?}", first.p50, first.p99, first.max);
+        println!("Inter-arrival p50: {:?}, p99: {:?}, max: {:?}", gaps.p50, gaps.p99, gaps.max);
+    }
+
+    if workload.retry.enabled() {
+        let retries = &measured.retries;
+        println!("\n=== Retries ===");
+        println!("Max attempts per call: {}", workload.retry.max_attempts);
+        println!(
+            "Retried calls: {} ({} retries), {} recovered",
+            retries.retried_calls, retries.retries, retries.recovered
+        );
+        println!("Throttled by retry budget: {}", retries.throttled);
+        println!("Server pushbacks: {}", retries.pushbacks);
+    }
+
+    if let Some(hedge) = &workload.hedge {
+        // Counted over the whole run, not just the measured phase
+        println!("\n=== Hedging ===");
+        match (hedge.after, hedge.delay()) {
+            (HedgeDelay::Percentile(_), Some(delay)) => {
+                println!("Hedge after: {} (last {:?}), at most {} of calls", hedge.after, delay, hedge.max_fraction)
+            }
+            _ => println!("Hedge after: {}, at most {} of calls", hedge.after, hedge.max_fraction),
         }
-        
-        for (error, count) in error_counts {
-            println!("  {}: {} occurrences", error, count);
+        println!(
+            "Hedged calls: {} of {} ({:.2}%)",
+            hedge.hedged(),
+            hedge.calls(),
+            hedge.hedged() as f64 / hedge.calls().max(1) as f64 * 100.0
+        );
+        println!("Hedge answered first: {}", hedge.hedge_wins());
+    }
+
+    if !plan.ramp_up.is_zero() || !plan.warmup.is_zero() {
+        println!("\n=== Phases ===");
+        for phase in Phase::ALL {
+            let phase_duration = plan.phase_duration(phase, total_duration);
+            if phase_duration.is_zero() {
+                continue;
+            }
+            let stats = totals.phase(phase);
+            let summary = stats.latencies.summary(phase_duration);
+            println!(
+                "  {}: {} requests, {} errors, {:.1} req/s, p50 {:?}, p99 {:?}",
+                phase.name(),
+                stats.requests,
+                stats.errors,
+                summary.throughput(),
+                summary.p50,
+                summary.p99
+            );
+        }
+    }
+
+    if let Some(endpoints) = &outcome.endpoints {
+        let calls: u64 = endpoints.iter().map(|e| e.calls).sum();
+        println!("\n=== Endpoints ===");
+        for endpoint in endpoints {
+            println!(
+                "  {}: {} calls ({:.1}%), {} failed, ejected {} times{}",
+                endpoint.url,
+                endpoint.calls,
+                endpoint.calls as f64 / calls.max(1) as f64 * 100.0,
+                endpoint.failures,
+                endpoint.ejections,
+                if endpoint.ejected { ", ejected now" } else { "" }
+            );
         }
     }
 
+    if !measured.failures.is_empty() {
+        println!("\n=== Error Breakdown ===");
+        for (kind, code, bucket) in measured.failures.by_count() {
+            println!(
+                "  {} {:?}: {} ({:.1}%), first after {:?}",
+                kind.name(),
+                code,
+                bucket.count,
+                bucket.count as f64 / total_errors as f64 * 100.0,
+                bucket.first_seen
+            );
+            for example in &bucket.examples {
+                println!("    e.g. {}", example);
+            }
+        }
+    }
+}
+
+fn build_report(workload: &Workload, tls: &TlsArgs, outcome: &RunOutcome, stage: Option<StageInfo>) -> Report {
+    let RunOutcome { plan, total_duration, totals, .. } = outcome;
+    let streaming = workload.streams_replies();
+    Report {
+        config: RunConfig {
+            server: workload.target(),
+            rpc: workload.rpc_name(),
+            clients: workload.clients,
+            connections: workload.connections,
+            requests: (workload.duration.is_none() && workload.replay.is_none()).then_some(workload.requests),
+            duration_secs: workload.duration.map(|d| d.as_secs_f64()),
+            warmup_secs: workload.warmup.as_secs_f64(),
+            ramp_up_secs: workload.ramp_up.as_secs_f64(),
+            messages: workload.messages,
+            rate: workload.rate,
+            tls: workload.endpoint_urls().unwrap_or_default().iter().any(|url| tls.enabled_for(url)),
+            replay: workload.replay.as_ref().map(|r| r.path.display().to_string()),
+            speed: workload.replay.as_ref().map(|r| r.speed),
+            max_attempts: workload.retry.enabled().then_some(workload.retry.max_attempts),
+            hedge_after: workload.hedge.as_ref().map(|h| h.after.to_string()),
+            hedge_max_fraction: workload.hedge.as_ref().map(|h| h.max_fraction),
+        },
+        stage,
+        started_at: report::timestamp(outcome.started_at),
+        finished_at: report::timestamp(outcome.finished_at),
+        total_duration_secs: total_duration.as_secs_f64(),
+        phases: Phase::ALL
+            .into_iter()
+            .filter(|&phase| phase == Phase::Measure || !plan.phase_duration(phase, *total_duration).is_zero())
+            .map(|phase| {
+                let duration = plan.phase_duration(phase, *total_duration);
+                PhaseReport::new(
+                    phase.name(),
+                    duration,
+                    totals.phase(phase),
+                    workload.open_loop(),
+                    streaming,
+                    workload.retry.enabled(),
+                )
+            })
+            .collect(),
+        throughput: report::throughput_samples(totals.throughput.seconds()),
+        peak_open_streams: streaming.then(stats::peak_open_streams),
+        endpoints: outcome.endpoints.as_ref().map(|endpoints| {
+            endpoints
+                .iter()
+                .map(|e| EndpointReport {
+                    url: e.url.clone(),
+                    calls: e.calls,
+                    failures: e.failures,
+                    ejections: e.ejections,
+                })
+                .collect()
+        }),
+        hedging: workload.hedge.as_ref().map(|hedge| HedgeReport {
+            calls: hedge.calls(),
+            hedged: hedge.hedged(),
+            hedge_wins: hedge.hedge_wins(),
+        }),
+    }
+}
+
+/// Saves the result for `compare` unless `--no-save`, and writes the
+/// `--output` report if one was asked for.
+fn save(args: &Args, report: &Report) -> std::io::Result<()> {
+    let stage = report.stage.as_ref().map(|s| s.name.as_str());
+    if !args.no_save {
+        std::fs::create_dir_all(&args.results_dir)?;
+        let path = args.results_dir.join(report::result_file_name(&report.started_at, stage));
+        report.write(OutputFormat::Json, &path)?;
+        println!("\nResult saved to {}", path.display());
+    }
+    if let Some(format) = args.output {
+        let path = args.output_file.clone().unwrap_or_else(|| format.default_path().into());
+        let path = match stage {
+            Some(stage) => report::stage_path(&path, stage),
+            None => path,
+        };
+        report.write(format, &path)?;
+        println!("Report written to {}", path.display());
+    }
     Ok(())
 }
 
-#[derive(Debug)]
+/// Per-phase outcomes of one client, or of all clients once merged.
+#[derive(Debug, Default)]
 struct BenchmarkResult {
-    requests: usize,
-    errors: usize,
-    error_details: Vec<String>,
+    phases: [PhaseStats; 3],
+    throughput: ThroughputSeries,
+    /// Also told about every call when a dashboard is showing
+    live: Option<Arc<LiveStats>>,
+}
+
+impl BenchmarkResult {
+    fn new(live: Option<Arc<LiveStats>>) -> Self {
+        Self {
+            live,
+            ..Default::default()
+        }
+    }
+
+    fn phase(&self, phase: Phase) -> &PhaseStats {
+        &self.phases[phase as usize]
+    }
+
+    fn phase_mut(&mut self, phase: Phase) -> &mut PhaseStats {
+        &mut self.phases[phase as usize]
+    }
+
+    /// Counts a call as in flight on the dashboard, if there is one.
+    fn sent(&self, client_id: usize) {
+        if let Some(live) = &self.live {
+            live.sent(client_id);
+        }
+    }
+
+    /// Records a finished call in its phase and in the throughput series.
+    fn record(
+        &mut self,
+        plan: &RunPlan,
+        phase: Phase,
+        client_id: usize,
+        request: usize,
+        outcome: Result<(Duration, Option<StreamTimings>), tonic::Status>,
+        retry: RetryStats,
+    ) {
+        let at = plan.start.elapsed();
+        if let Some(live) = &self.live {
+            live.finished(client_id, phase, outcome.as_ref().map(|(latency, _)| *latency));
+        }
+        self.throughput.record(at.as_secs() as usize, outcome.is_ok());
+        self.phase_mut(phase).record(client_id, request, at, outcome, retry);
+    }
+
+    fn merge(&mut self, other: BenchmarkResult) {
+        for (phase, stats) in Phase::ALL.into_iter().zip(other.phases) {
+            self.phase_mut(phase).merge(stats);
+        }
+        self.throughput.merge(&other.throughput);
+    }
+}
+
+/// Open-loop timetable shared by all clients: client `c` sends its `i`-th
+/// call at `(c + i * clients) / rate` seconds, so sends are evenly spaced overall.
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    rate: f64,
+    clients: usize,
+}
+
+impl Schedule {
+    /// `None` once the call is too far out to represent, i.e. never due.
+    fn offset(&self, client_id: usize, request: usize) -> Option<Duration> {
+        let slot = request.checked_mul(self.clients)?.checked_add(client_id)?;
+        Duration::try_from_secs_f64(slot as f64 / self.rate).ok()
+    }
+}
+
+/// Opens `workload.connections` connections to every server concurrently,
+/// and balances each connection's calls over the servers. A connection
+/// fails only if no server could be reached on it; a server that failed is
+/// retried lazily and ejected while its calls keep failing.
+async fn connect_all(workload: &Workload, tls: &TlsArgs) -> (Balancer, Vec<Result<BalancedChannel, String>>) {
+    let count = workload.connections;
+    let balancer = Balancer::new(workload.balance);
+    let urls = match workload.endpoint_urls() {
+        Ok(urls) => urls,
+        Err(e) => {
+            error!("No servers to connect to: {}", e);
+            return (balancer, vec![Err(e); count]);
+        }
+    };
+    if urls.len() > 1 || workload.endpoints_file.is_some() {
+        info!("Balancing {:?} over {}", workload.balance.policy, urls.join(", "));
+    }
+
+    let started = Instant::now();
+    let mut connecting = JoinSet::new();
+    for (server, url) in urls.iter().enumerate() {
+        for index in 0..count {
+            let server_url = url.clone();
+            let tls = tls.clone();
+            connecting.spawn(async move {
+                let channel = match tls.endpoint(server_url) {
+                    Ok(endpoint) => {
+                        let endpoint = endpoint.timeout(Duration::from_secs(60));
+                        endpoint.connect().await.map_err(|e| (errors::describe(&e), Some(endpoint)))
+                    }
+                    Err(e) => Err((errors::describe(e.as_ref()), None)),
+                };
+                (server, index, channel)
+            });
+        }
+    }
+
+    let mut channels = vec![vec![None; count]; urls.len()];
+    let mut failures = vec![None; count];
+    let mut reached = vec![0; count];
+    while let Some(joined) = connecting.join_next().await {
+        let (server, index, channel) = joined.expect("connect task panicked");
+        let channel = match channel {
+            Ok(channel) => {
+                reached[index] += 1;
+                channel
+            }
+            Err((e, endpoint)) => {
+                if urls.len() > 1 {
+                    error!("Connection {} to {} failed: {}", index, urls[server], e);
+                } else {
+                    error!("Connection {} failed: {}", index, e);
+                }
+                failures[index].get_or_insert(e);
+                // Unusable URLs are left out; unreachable servers are retried on use
+                match endpoint {
+                    Some(endpoint) => endpoint.connect_lazy(),
+                    None => continue,
+                }
+            }
+        };
+        channels[server][index] = Some(channel);
+    }
+    let opened: usize = reached.iter().sum();
+    info!("Opened {} of {} connections in {:?}", opened, count * urls.len(), started.elapsed());
+
+    for (url, channels) in urls.iter().zip(channels) {
+        if let Some(channels) = channels.into_iter().collect::<Option<Vec<_>>>() {
+            balancer.insert(url.clone(), channels);
+        }
+    }
+    if let Some(path) = &workload.endpoints_file {
+        let tls = tls.clone();
+        balancer.watch(path.clone(), move |url| {
+            let endpoint = tls.endpoint(url).map_err(|e| errors::describe(e.as_ref()))?;
+            let endpoint = endpoint.timeout(Duration::from_secs(60));
+            Ok((0..count).map(|_| endpoint.connect_lazy()).collect())
+        });
+    }
+
+    // A connection failed if no server could be reached on it
+    let connections = (0..count)
+        .zip(failures)
+        .map(|(index, failure)| match failure {
+            Some(e) if reached[index] == 0 => Err(e),
+            _ => Ok(balancer.connection(index)),
+        })
+        .collect();
+    (balancer, connections)
 }
 
 async fn benchmark_client(
     client_id: usize,
-    server_url: String,
-    requests: usize,
+    channel: Result<BalancedChannel, String>,
+    plan: RunPlan,
+    workload: Arc<Workload>,
+    live: Option<Arc<LiveStats>>,
 ) -> BenchmarkResult {
-    let mut error_details = Vec::new();
-    
-    // Create basic connection
-    let channel_result = Channel::from_shared(server_url)
-        .unwrap()
-        .timeout(Duration::from_secs(60))
-        .connect()
-        .await;
-
-    let mut client = match channel_result {
+    let mut result = BenchmarkResult::new(live);
+
+    let client = match channel {
         Ok(channel) => GreeterServiceClient::new(channel),
         Err(e) => {
-            error!("Client {} failed to connect: {:?}", client_id, e);
-            error_details.push(format!("Connection failed: {}", e));
-            return BenchmarkResult {
-                requests,
-                errors: requests,
-                error_details,
-            };
+            error!("Client {} failed to connect: {}", client_id, e);
+            // A duration run has no request count, so the client counts as one failure
+            let failed = if plan.duration.is_some() { 1 } else { plan.requests };
+            let measured = result.phase_mut(Phase::Measure);
+            measured.requests = failed;
+            measured.errors = failed;
+            measured.failures.record_connect(&e, failed);
+            return result;
         }
     };
 
-    let mut errors = 0;
+    let client_start = plan.client_start(client_id);
+    tokio::time::sleep_until(client_start).await;
+    let mut measured = 0;
 
-    for i in 0..requests {
-        let request = tonic::Request::new(HelloRequest {
-            name: format!("Client-{}-Request-{}", client_id, i),
-        });
-        
-        match client.say_hello(request).await {
-            Ok(_) => {},
-            Err(e) => {
-                errors += 1;
-                let error_msg = format!("Client {} req {}: {}", client_id, i, e);
-                error_details.push(error_msg.clone());
-                if errors <= 5 {
-                    warn!("{}", error_msg);
+    let schedule = workload.rate.map(|rate| Schedule {
+        rate,
+        clients: workload.clients,
+    });
+    match schedule {
+        None => {
+            for i in 0.. {
+                let sent = Instant::now();
+                if plan.finished(sent, measured) {
+                    break;
                 }
+                let phase = plan.phase_at(sent);
+                if phase == Phase::Measure {
+                    measured += 1;
+                }
+                let name = workload.payload.name(client_id, i);
+                result.sent(client_id);
+                let (outcome, retry) =
+                    call_with_policies(&client, &workload, workload.rpc, name, workload.messages, &workload.metadata).await;
+                result.record(&plan, phase, client_id, i, outcome.map(|stream| (sent.elapsed(), stream)), retry);
+            }
+        }
+        Some(schedule) => {
+            // Calls run in their own tasks so a slow response never delays the
+            // next send; latency counts from the scheduled time, which includes
+            // any queueing a closed loop would hide.
+            let mut calls = JoinSet::new();
+            for i in 0.. {
+                let Some(scheduled) = schedule.offset(client_id, i).and_then(|offset| client_start.checked_add(offset))
+                else {
+                    break;
+                };
+                if plan.finished(scheduled, measured) {
+                    break;
+                }
+                let phase = plan.phase_at(scheduled);
+                if phase == Phase::Measure {
+                    measured += 1;
+                }
+                tokio::time::sleep_until(scheduled).await;
+                result.phase_mut(phase).send_lag.record(scheduled.elapsed());
+                result.sent(client_id);
+
+                let client = client.clone();
+                let workload = workload.clone();
+                calls.spawn(async move {
+                    let name = workload.payload.name(client_id, i);
+                    let (outcome, retry) =
+                        call_with_policies(&client, &workload, workload.rpc, name, workload.messages, &workload.metadata)
+                            .await;
+                    (i, phase, outcome.map(|stream| (scheduled.elapsed(), stream)), retry)
+                });
+            }
+            while let Some(joined) = calls.join_next().await {
+                let (i, phase, outcome, retry) = joined.expect("benchmark call task panicked");
+                result.record(&plan, phase, client_id, i, outcome, retry);
             }
         }
     }
 
     info!("Client {} completed {} requests with {} errors", 
-          client_id, requests, errors);
+          client_id, measured, result.phase(Phase::Measure).errors);
+
+    result
+}
+
+/// Sends each recorded call when it is due, spreading them round-robin over
+/// the connections, with at most `in_flight` calls outstanding.
+async fn replay_calls(
+    replay: Arc<Replay>,
+    workload: Arc<Workload>,
+    channels: Vec<Result<BalancedChannel, String>>,
+    plan: RunPlan,
+    in_flight: usize,
+    live: Option<Arc<LiveStats>>,
+) -> BenchmarkResult {
+    let mut result = BenchmarkResult::new(live);
+    let clients: Vec<_> = channels.into_iter().map(|c| c.map(GreeterServiceClient::new)).collect();
+    let permits = Arc::new(Semaphore::new(in_flight));
+    let mut calls = JoinSet::new();
+
+    for i in 0..replay.calls.len() {
+        let connection = i % clients.len();
+        let scheduled = plan.start + replay.due(&replay.calls[i]);
+        tokio::time::sleep_until(scheduled).await;
+        let permit = permits.clone().acquire_owned().await.expect("replay permits closed");
+        let measured = result.phase_mut(Phase::Measure);
+        measured.send_lag.record(scheduled.elapsed());
+
+        let client = match &clients[connection] {
+            Ok(client) => client.clone(),
+            Err(e) => {
+                measured.requests += 1;
+                measured.errors += 1;
+                measured.failures.record_connect(e, 1);
+                continue;
+            }
+        };
+        result.sent(connection);
+        let replay = replay.clone();
+        let workload = workload.clone();
+        calls.spawn(async move {
+            let recorded = &replay.calls[i];
+            let (outcome, retry) =
+                call_with_policies(&client, &workload, recorded.rpc, recorded.name.clone(), 0, &recorded.metadata).await;
+            drop(permit);
+            (connection, i, outcome.map(|stream| (scheduled.elapsed(), stream)), retry)
+        });
+
+        // Collect as we go so long recordings do not pile up finished tasks
+        while let Some(joined) = calls.try_join_next() {
+            let (connection, i, outcome, retry) = joined.expect("replay call task panicked");
+            result.record(&plan, Phase::Measure, connection, i, outcome, retry);
+        }
+    }
+    while let Some(joined) = calls.join_next().await {
+        let (connection, i, outcome, retry) = joined.expect("replay call task panicked");
+        result.record(&plan, Phase::Measure, connection, i, outcome, retry);
+    }
+
+    info!("Replayed {} calls with {} errors", replay.calls.len(), result.phase(Phase::Measure).errors);
+    result
+}
+
+/// `call`, hedged if it is unary and retried, as `workload` says. A
+/// streaming call that fails part-way is retried from the start.
+async fn call_with_policies(
+    client: &GreeterServiceClient<BalancedChannel>,
+    workload: &Workload,
+    rpc: Rpc,
+    name: String,
+    messages: usize,
+    metadata: &MetadataMap,
+) -> (Result<Option<StreamTimings>, tonic::Status>, RetryStats) {
+    let attempt = || {
+        let mut client = client.clone();
+        let name = name.clone();
+        async move { call(&mut client, rpc, name, messages, metadata).await }
+    };
+    workload
+        .retry
+        .call(|| async {
+            match (&workload.hedge, rpc) {
+                (Some(hedge), Rpc::Unary) => hedge.call(attempt).await.0,
+                _ => attempt().await,
+            }
+        })
+        .await
+}
+
+/// Issues one call of `rpc`, draining any response stream. Returns the
+/// arrival timings of the response messages for the RPCs that stream them
+/// back.
+async fn call(
+    client: &mut GreeterServiceClient<BalancedChannel>,
+    rpc: Rpc,
+    name: String,
+    messages: usize,
+    metadata: &MetadataMap,
+) -> Result<Option<StreamTimings>, tonic::Status> {
+    let sent = std::time::Instant::now();
+    match rpc {
+        Rpc::Unary => {
+            client.say_hello(workload::request(HelloRequest { name }, metadata)).await?;
+            Ok(None)
+        }
+        Rpc::ServerStream => {
+            let replies = client.say_hello_stream(workload::request(HelloRequest { name }, metadata)).await?.into_inner();
+            Ok(Some(time_stream(replies, sent).await?))
+        }
+        Rpc::ClientStream => {
+            let outbound = tokio_stream::iter(0..messages).map(move |m| HelloRequest {
+                name: format!("{}-{}", name, m),
+            });
+            client.say_hello_client_stream(workload::request(outbound, metadata)).await?;
+            Ok(None)
+        }
+        Rpc::Chat => {
+            let outbound = tokio_stream::iter(0..messages).map(move |m| HelloRequest {
+                name: format!("{}-{}", name, m),
+            });
+            let replies = client.say_hello_chat(workload::request(outbound, metadata)).await?.into_inner();
+            Ok(Some(time_stream(replies, sent).await?))
+        }
+    }
+}
+
+/// Drains a response stream, noting when each message arrived.
+async fn time_stream(
+    mut replies: tonic::Streaming<HelloResponse>,
+    sent: std::time::Instant,
+) -> Result<StreamTimings, tonic::Status> {
+    let _open = OpenStream::open();
+    let mut timings = StreamTimings::default();
+    let mut last = sent;
+    while let Some(reply) = replies.next().await {
+        reply?;
+        let now = std::time::Instant::now();
+        if timings.first_message.is_none() {
+            timings.first_message = Some(now - sent);
+        } else {
+            timings.inter_arrival.push(now - last);
+        }
+        last = now;
+    }
+    Ok(timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations_in_every_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("0.25m"), Ok(Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn rejects_durations_that_are_not_finite_and_positive() {
+        for input in ["", "s", "ms", "-1s", "-0.5", "NaNs", "infs", "inf", "1e300s", "1e300m", "10h", "fast"] {
+            let error = parse_duration(input).unwrap_err();
+            assert!(error.contains("is not a duration"), "{:?}: {}", input, error);
+        }
+    }
+
+    #[test]
+    fn schedule_interleaves_clients() {
+        let schedule = Schedule { rate: 8.0, clients: 4 };
+        assert_eq!(schedule.offset(0, 0), Some(Duration::ZERO));
+        assert_eq!(schedule.offset(1, 0), Some(Duration::from_millis(125)));
+        assert_eq!(schedule.offset(3, 0), Some(Duration::from_millis(375)));
+        assert_eq!(schedule.offset(0, 1), Some(Duration::from_millis(500)));
+        assert_eq!(schedule.offset(2, 3), Some(Duration::from_millis(1750)));
+    }
+
+    #[test]
+    fn schedule_spaces_all_sends_evenly() {
+        let schedule = Schedule { rate: 16.0, clients: 3 };
+        let mut offsets: Vec<_> = (0..3)
+            .flat_map(|client| (0..10).map(move |request| schedule.offset(client, request).unwrap()))
+            .collect();
+        offsets.sort();
+        for (slot, offset) in offsets.iter().enumerate() {
+            assert_eq!(*offset, Duration::from_secs_f64(slot as f64 / 16.0));
+        }
+    }
+
+    #[test]
+    fn a_single_client_sends_at_the_full_rate() {
+        let schedule = Schedule { rate: 0.5, clients: 1 };
+        assert_eq!(schedule.offset(0, 3), Some(Duration::from_secs(6)));
+    }
 
-    BenchmarkResult {
-        requests,
-        errors,
-        error_details,
+    #[test]
+    fn calls_too_far_out_are_never_due() {
+        let schedule = Schedule { rate: 1e-300, clients: 2 };
+        assert_eq!(schedule.offset(0, 0), Some(Duration::ZERO));
+        assert_eq!(schedule.offset(1, 0), None);
+        let schedule = Schedule { rate: 1.0, clients: usize::MAX };
+        assert_eq!(schedule.offset(0, 2), None);
     }
 }
@@ -0,0 +1,211 @@
+//! Run phases: clients join during ramp-up, warm connections up, and only
+//! then does the measured phase start.
+
+use crate::errors::ErrorTally;
+use crate::stats::{LatencyHistogram, RetryTally, StreamStats, StreamTimings};
+use grpc_demo_client::RetryStats;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    RampUp,
+    Warmup,
+    Measure,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::RampUp, Phase::Warmup, Phase::Measure];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::RampUp => "ramp-up",
+            Phase::Warmup => "warmup",
+            Phase::Measure => "measure",
+        }
+    }
+}
+
+/// When each phase starts and when the run ends, shared by every client.
+#[derive(Debug, Clone, Copy)]
+pub struct RunPlan {
+    pub start: Instant,
+    pub clients: usize,
+    pub ramp_up: Duration,
+    pub warmup: Duration,
+    /// Length of the measured phase; `None` runs a fixed number of measured
+    /// calls per client instead.
+    pub duration: Option<Duration>,
+    /// Measured calls per client when `duration` is `None`.
+    pub requests: usize,
+}
+
+impl RunPlan {
+    /// When `client_id` begins sending; clients join evenly over the ramp-up.
+    pub fn client_start(&self, client_id: usize) -> Instant {
+        self.start + self.ramp_up.mul_f64(client_id as f64 / self.clients as f64)
+    }
+
+    pub fn measure_start(&self) -> Instant {
+        self.start + self.ramp_up + self.warmup
+    }
+
+    pub fn phase_at(&self, at: Instant) -> Phase {
+        if at < self.start + self.ramp_up {
+            Phase::RampUp
+        } else if at < self.measure_start() {
+            Phase::Warmup
+        } else {
+            Phase::Measure
+        }
+    }
+
+    /// Whether a client that has made `measured` measured calls should stop
+    /// instead of sending at `at`.
+    pub fn finished(&self, at: Instant, measured: usize) -> bool {
+        match self.duration {
+            Some(duration) => at >= self.measure_start() + duration,
+            None => measured >= self.requests,
+        }
+    }
+
+    /// Wall-clock length of `phase`, given how long the whole run took.
+    pub fn phase_duration(&self, phase: Phase, total: Duration) -> Duration {
+        match phase {
+            Phase::RampUp => self.ramp_up,
+            Phase::Warmup => self.warmup,
+            Phase::Measure => total.saturating_sub(self.ramp_up + self.warmup),
+        }
+    }
+}
+
+/// Outcomes of the calls sent during one phase.
+#[derive(Debug, Default)]
+pub struct PhaseStats {
+    pub requests: usize,
+    pub errors: usize,
+    /// Failed calls by kind and gRPC status code
+    pub failures: ErrorTally,
+    /// Latencies of the successful calls
+    pub latencies: LatencyHistogram,
+    /// Open loop only: how long after its scheduled time each call was sent
+    pub send_lag: LatencyHistogram,
+    /// Response-stream timings of the successful streaming calls
+    pub streams: StreamStats,
+    pub retries: RetryTally,
+}
+
+impl PhaseStats {
+    /// Records a call that finished `at` after the start of the run.
+    pub fn record(
+        &mut self,
+        client_id: usize,
+        request: usize,
+        at: Duration,
+        outcome: Result<(Duration, Option<StreamTimings>), tonic::Status>,
+        retry: RetryStats,
+    ) {
+        self.requests += 1;
+        self.retries.record(&retry, outcome.is_ok());
+        match outcome {
+            Ok((latency, stream)) => {
+                self.latencies.record(latency);
+                if let Some(timings) = stream {
+                    self.streams.record(&timings);
+                }
+            }
+            Err(e) => {
+                self.errors += 1;
+                self.failures.record(&e, at);
+                if self.errors <= 5 {
+                    warn!("Client {} req {}: {}", client_id, request, e);
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: PhaseStats) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.failures.merge(other.failures);
+        self.latencies.merge(&other.latencies);
+        self.send_lag.merge(&other.send_lag);
+        self.streams.merge(&other.streams);
+        self.retries.merge(&other.retries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(ramp_up: u64, warmup: u64, duration: Option<u64>) -> RunPlan {
+        RunPlan {
+            start: Instant::now(),
+            clients: 4,
+            ramp_up: Duration::from_secs(ramp_up),
+            warmup: Duration::from_secs(warmup),
+            duration: duration.map(Duration::from_secs),
+            requests: 10,
+        }
+    }
+
+    #[test]
+    fn clients_join_evenly_over_the_ramp_up() {
+        let plan = plan(8, 0, None);
+        let starts: Vec<_> = (0..4).map(|client| plan.client_start(client) - plan.start).collect();
+        assert_eq!(starts, [0, 2, 4, 6].map(Duration::from_secs));
+    }
+
+    #[test]
+    fn without_a_ramp_up_every_client_starts_at_once() {
+        let plan = plan(0, 5, None);
+        assert!((0..4).all(|client| plan.client_start(client) == plan.start));
+    }
+
+    #[test]
+    fn phases_change_at_their_boundaries() {
+        let plan = plan(2, 3, Some(10));
+        let at = |secs: f64| plan.start + Duration::from_secs_f64(secs);
+        assert_eq!(plan.phase_at(at(0.0)), Phase::RampUp);
+        assert_eq!(plan.phase_at(at(1.999)), Phase::RampUp);
+        assert_eq!(plan.phase_at(at(2.0)), Phase::Warmup);
+        assert_eq!(plan.phase_at(at(4.999)), Phase::Warmup);
+        assert_eq!(plan.phase_at(at(5.0)), Phase::Measure);
+        assert_eq!(plan.phase_at(at(500.0)), Phase::Measure);
+        assert_eq!(plan.measure_start(), at(5.0));
+    }
+
+    #[test]
+    fn without_ramp_up_or_warmup_everything_is_measured() {
+        let plan = plan(0, 0, None);
+        assert_eq!(plan.phase_at(plan.start), Phase::Measure);
+    }
+
+    #[test]
+    fn a_timed_run_finishes_after_the_measured_duration() {
+        let plan = plan(2, 3, Some(10));
+        let at = |secs| plan.start + Duration::from_secs(secs);
+        assert!(!plan.finished(at(14), 1_000_000));
+        assert!(plan.finished(at(15), 0));
+        assert!(plan.finished(at(16), 0));
+    }
+
+    #[test]
+    fn a_counted_run_finishes_after_its_measured_requests() {
+        let plan = plan(2, 3, None);
+        assert!(!plan.finished(plan.start + Duration::from_secs(3600), 9));
+        assert!(plan.finished(plan.start, 10));
+    }
+
+    #[test]
+    fn the_measured_phase_lasts_what_is_left_of_the_run() {
+        let plan = plan(2, 3, Some(10));
+        let total = Duration::from_secs(16);
+        assert_eq!(plan.phase_duration(Phase::RampUp, total), Duration::from_secs(2));
+        assert_eq!(plan.phase_duration(Phase::Warmup, total), Duration::from_secs(3));
+        assert_eq!(plan.phase_duration(Phase::Measure, total), Duration::from_secs(11));
+        assert_eq!(plan.phase_duration(Phase::Measure, Duration::from_secs(1)), Duration::ZERO);
+    }
+}
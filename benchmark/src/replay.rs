@@ -0,0 +1,262 @@
+//! Recorded traffic for `--replay`: one JSON object per line, as written by
+//! the server's `--record-traffic`:
+//!
+//! ```json
+//! {"offset_ms":1520.25,"rpc":"unary","name":"alice","metadata":{"x-tenant":"a"}}
+//! ```
+//!
+//! Only `name` is required. `rpc` defaults to `unary`. A line without
+//! `offset_ms` is sent right after the line before it.
+
+use crate::workload::{metadata_map, Rpc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tonic::metadata::MetadataMap;
+
+/// Longest a replay may take once sped up, so every call has a send time.
+const MAX_SPAN: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug)]
+pub struct Replay {
+    pub path: PathBuf,
+    /// How many times faster than recorded the calls are sent.
+    pub speed: f64,
+    /// Sorted by `offset`.
+    pub calls: Vec<RecordedCall>,
+}
+
+#[derive(Debug)]
+pub struct RecordedCall {
+    /// When the call was sent, relative to the first recorded call.
+    pub offset: Duration,
+    pub rpc: Rpc,
+    pub name: String,
+    pub metadata: MetadataMap,
+}
+
+#[derive(Deserialize)]
+struct Line {
+    name: String,
+    #[serde(default)]
+    rpc: Rpc,
+    offset_ms: Option<f64>,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+impl Replay {
+    pub fn load(path: &Path, speed: f64) -> Result<Self, ReplayError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ReplayError::Read(path.to_path_buf(), e))?;
+        let invalid = |line: usize, msg: String| ReplayError::Invalid(path.to_path_buf(), line, msg);
+
+        let mut calls = Vec::new();
+        let mut previous = 0.0;
+        for (index, text) in text.lines().enumerate() {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let line: Line = serde_json::from_str(text).map_err(|e| invalid(index + 1, e.to_string()))?;
+            if !matches!(line.rpc, Rpc::Unary | Rpc::ServerStream) {
+                return Err(invalid(index + 1, "only unary and server-stream calls can be replayed".to_string()));
+            }
+            let offset_ms = line.offset_ms.unwrap_or(previous);
+            if !offset_ms.is_finite() || offset_ms < 0.0 {
+                return Err(invalid(index + 1, format!("offset_ms {} is not a time since the start", offset_ms)));
+            }
+            previous = offset_ms;
+            let offset = Duration::try_from_secs_f64(offset_ms / 1000.0)
+                .map_err(|_| invalid(index + 1, "offset_ms is too large to replay".to_string()))?;
+            let call = RecordedCall {
+                offset,
+                rpc: line.rpc,
+                name: line.name,
+                metadata: metadata_map(&line.metadata).map_err(|e| invalid(index + 1, e))?,
+            };
+            calls.push((index + 1, call));
+        }
+        if calls.is_empty() {
+            return Err(ReplayError::Empty(path.to_path_buf()));
+        }
+
+        // Concurrent handlers can write lines slightly out of order
+        calls.sort_by_key(|(_, call)| call.offset);
+        let first = calls[0].1.offset;
+        for (_, call) in &mut calls {
+            call.offset -= first;
+        }
+        let (last_line, last) = &calls[calls.len() - 1];
+        let span = Duration::try_from_secs_f64(last.offset.as_secs_f64() / speed);
+        if !span.is_ok_and(|span| span <= MAX_SPAN) {
+            let msg = "at this speed the call would be sent more than a year after the first";
+            return Err(invalid(*last_line, msg.to_string()));
+        }
+        let calls = calls.into_iter().map(|(_, call)| call).collect();
+        Ok(Replay {
+            path: path.to_path_buf(),
+            speed,
+            calls,
+        })
+    }
+
+    /// When `call` is due, relative to the start of the replay.
+    pub fn due(&self, call: &RecordedCall) -> Duration {
+        call.offset.div_f64(self.speed)
+    }
+
+    /// Length of the recording once sped up.
+    pub fn span(&self) -> Duration {
+        self.calls.last().map_or(Duration::ZERO, |call| self.due(call))
+    }
+
+    pub fn streams_replies(&self) -> bool {
+        self.calls.iter().any(|call| call.rpc.streams_replies())
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Read(PathBuf, std::io::Error),
+    Invalid(PathBuf, usize, String),
+    Empty(PathBuf),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ReplayError::Invalid(path, line, msg) => write!(f, "{} line {}: {}", path.display(), line, msg),
+            ReplayError::Empty(path) => write!(f, "{} contains no calls to replay", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::Read(_, e) => Some(e),
+            ReplayError::Invalid(..) | ReplayError::Empty(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("grpc-demo-replay-{}-{}.jsonl", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load(name: &str, speed: f64, contents: &str) -> Result<Replay, ReplayError> {
+        let path = temp_file(name, contents);
+        let result = Replay::load(&path, speed);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    /// The line number and message of a recording that must be rejected.
+    fn invalid(name: &str, speed: f64, contents: &str) -> (usize, String) {
+        match load(name, speed, contents) {
+            Err(ReplayError::Invalid(_, line, msg)) => (line, msg),
+            other => panic!("expected an invalid recording, got {:?}", other),
+        }
+    }
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_lines_with_defaults() {
+        let replay = load(
+            "defaults",
+            1.0,
+            concat!(
+                r#"{"offset_ms":10,"rpc":"server-stream","name":"alice","metadata":{"x-tenant":"a"}}"#,
+                "\n\n",
+                r#"{"name":"bob"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let [alice, bob] = &replay.calls[..] else { panic!("expected two calls") };
+        assert!(matches!(alice.rpc, Rpc::ServerStream));
+        assert_eq!(alice.metadata.get("x-tenant").unwrap(), "a");
+        assert!(matches!(bob.rpc, Rpc::Unary));
+        assert_eq!(bob.name, "bob");
+        assert!(bob.metadata.is_empty());
+        // Without an offset, bob is sent right after alice
+        assert_eq!(bob.offset, alice.offset);
+        assert!(replay.streams_replies());
+    }
+
+    #[test]
+    fn sorts_calls_and_starts_at_the_first() {
+        let replay = load(
+            "sorted",
+            1.0,
+            concat!(
+                r#"{"offset_ms":1250.5,"name":"c"}"#,
+                "\n",
+                r#"{"offset_ms":1000,"name":"a"}"#,
+                "\n",
+                r#"{"offset_ms":1100,"name":"b"}"#,
+            ),
+        )
+        .unwrap();
+        let calls: Vec<_> = replay.calls.iter().map(|call| (call.name.as_str(), call.offset)).collect();
+        assert_eq!(calls, [("a", Duration::ZERO), ("b", millis(100)), ("c", Duration::from_micros(250_500))]);
+        assert!(!replay.streams_replies());
+    }
+
+    #[test]
+    fn speed_scales_when_calls_are_due() {
+        let contents = concat!(r#"{"offset_ms":0,"name":"a"}"#, "\n", r#"{"offset_ms":1000,"name":"b"}"#);
+        let faster = load("faster", 4.0, contents).unwrap();
+        assert_eq!(faster.due(&faster.calls[1]), millis(250));
+        assert_eq!(faster.span(), millis(250));
+        let slower = load("slower", 0.5, contents).unwrap();
+        assert_eq!(slower.due(&slower.calls[0]), Duration::ZERO);
+        assert_eq!(slower.span(), millis(2000));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_call() {
+        let ok = r#"{"offset_ms":0,"name":"a"}"#;
+        let cases = [
+            ("json", r#"{"offset_ms":1"#, "EOF while parsing"),
+            ("chat", r#"{"rpc":"chat","name":"b"}"#, "only unary and server-stream calls can be replayed"),
+            ("negative", r#"{"offset_ms":-1,"name":"b"}"#, "offset_ms -1 is not a time since the start"),
+            ("huge", r#"{"offset_ms":1e300,"name":"b"}"#, "offset_ms is too large to replay"),
+            ("metadata", r#"{"name":"b","metadata":{"bad key":"v"}}"#, "'bad key' is not a valid ASCII metadata key"),
+        ];
+        for (name, line, expected) in cases {
+            let (number, msg) = invalid(name, 1.0, &format!("{}\n\n{}\n", ok, line));
+            assert_eq!(number, 3, "{}", name);
+            assert!(msg.contains(expected), "{}: {}", name, msg);
+        }
+    }
+
+    #[test]
+    fn rejects_a_speed_that_would_never_send_the_last_call() {
+        let contents = concat!(r#"{"offset_ms":1000,"name":"late"}"#, "\n", r#"{"offset_ms":0,"name":"a"}"#);
+        let (line, msg) = invalid("crawl", 1e-300, contents);
+        assert_eq!(line, 1);
+        assert!(msg.contains("more than a year after the first"), "{}", msg);
+        // A single call is always due straight away
+        let single = load("single", 1e-300, r#"{"name":"a"}"#).unwrap();
+        assert_eq!(single.span(), Duration::ZERO);
+    }
+
+    #[test]
+    fn an_empty_recording_has_nothing_to_replay() {
+        assert!(matches!(load("empty", 1.0, "\n  \n"), Err(ReplayError::Empty(_))));
+        let missing = Replay::load(Path::new("/nonexistent/calls.jsonl"), 1.0);
+        assert!(matches!(missing, Err(ReplayError::Read(..))));
+    }
+}
@@ -0,0 +1,542 @@
+//! Machine-readable run reports: saved as JSON after every run for
+//! `compare`, and written with `--output json|csv`.
+
+use crate::phase::PhaseStats;
+use crate::stats::{Completions, LatencySummary, RetryTally};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// One JSON document
+    Json,
+    /// `section,name,field,value` rows
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "benchmark-report.json",
+            OutputFormat::Csv => "benchmark-report.csv",
+        }
+    }
+}
+
+/// Everything needed to reproduce or chart a run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub config: RunConfig,
+    /// Scenario runs only: which stage this report covers.
+    #[serde(default)]
+    pub stage: Option<StageInfo>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub total_duration_secs: f64,
+    pub phases: Vec<PhaseReport>,
+    /// Calls completed in each second since `started_at`, across all phases.
+    pub throughput: Vec<ThroughputSample>,
+    /// Streaming RPCs only: most response streams open at once.
+    #[serde(default)]
+    pub peak_open_streams: Option<usize>,
+    /// Hedged runs only, across all phases.
+    #[serde(default)]
+    pub hedging: Option<HedgeReport>,
+    /// Balanced runs only: calls sent to each server, across all phases.
+    #[serde(default)]
+    pub endpoints: Option<Vec<EndpointReport>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndpointReport {
+    pub url: String,
+    /// Calls routed to the server, including retries and hedges.
+    pub calls: u64,
+    /// Calls that failed in transport or with `UNAVAILABLE`.
+    pub failures: u64,
+    pub ejections: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HedgeReport {
+    /// Unary calls made with hedging on.
+    pub calls: u64,
+    pub hedged: u64,
+    /// Hedged calls the hedge answered first.
+    pub hedge_wins: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StageInfo {
+    pub scenario: String,
+    /// Position in the scenario, from 0.
+    pub index: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunConfig {
+    pub server: String,
+    pub rpc: String,
+    pub clients: usize,
+    /// HTTP/2 connections the clients were spread over; 0 in results saved
+    /// before the option existed.
+    #[serde(default)]
+    pub connections: usize,
+    /// Measured calls per client; `None` for `--duration` runs.
+    pub requests: Option<usize>,
+    pub duration_secs: Option<f64>,
+    pub warmup_secs: f64,
+    pub ramp_up_secs: f64,
+    pub messages: usize,
+    /// Open-loop target rate in requests per second.
+    pub rate: Option<f64>,
+    pub tls: bool,
+    /// Replay runs only: the recording and how much faster it was sent.
+    #[serde(default)]
+    pub replay: Option<String>,
+    #[serde(default)]
+    pub speed: Option<f64>,
+    /// Attempts per call when retries were on.
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Hedged runs only: `20ms` or a percentile like `p95`, and the cap on
+    /// the share of calls hedged.
+    #[serde(default)]
+    pub hedge_after: Option<String>,
+    #[serde(default)]
+    pub hedge_max_fraction: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhaseReport {
+    pub phase: String,
+    pub duration_secs: f64,
+    pub requests: usize,
+    pub errors: usize,
+    /// Successful calls per second.
+    pub throughput: f64,
+    pub latency: LatencyReport,
+    /// Open-loop runs only: delay between scheduled and actual send time.
+    pub send_lag: Option<LatencyReport>,
+    /// Failed calls by gRPC status code name, e.g. `Unavailable`.
+    pub errors_by_code: BTreeMap<String, usize>,
+    /// Failed calls by kind and code, most frequent first.
+    #[serde(default)]
+    pub error_classes: Vec<ErrorClassReport>,
+    /// RPCs with a response stream only.
+    #[serde(default)]
+    pub streams: Option<StreamReport>,
+    /// Runs with retries only.
+    #[serde(default)]
+    pub retries: Option<RetryReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorClassReport {
+    /// `connect`, `transport` or `status`
+    pub kind: String,
+    pub code: String,
+    pub count: usize,
+    /// Seconds from the start of the run to the first failure.
+    pub first_seen_secs: f64,
+    pub examples: Vec<String>,
+}
+
+/// Response-stream timings of a phase.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamReport {
+    pub streams: u64,
+    pub messages: u64,
+    pub messages_per_sec: f64,
+    pub first_message: LatencyReport,
+    pub inter_arrival: LatencyReport,
+}
+
+/// What retrying did in a phase.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetryReport {
+    pub retried_calls: u64,
+    pub retries: u64,
+    pub recovered: u64,
+    pub throttled: u64,
+    pub pushbacks: u64,
+}
+
+impl From<&RetryTally> for RetryReport {
+    fn from(t: &RetryTally) -> Self {
+        Self {
+            retried_calls: t.retried_calls,
+            retries: t.retries,
+            recovered: t.recovered,
+            throttled: t.throttled,
+            pushbacks: t.pushbacks,
+        }
+    }
+}
+
+impl PhaseReport {
+    pub fn new(
+        phase: &'static str,
+        duration: Duration,
+        stats: &PhaseStats,
+        open_loop: bool,
+        streaming: bool,
+        retrying: bool,
+    ) -> Self {
+        let latency = stats.latencies.summary(duration);
+        let streams = &stats.streams;
+        Self {
+            phase: phase.to_string(),
+            duration_secs: duration.as_secs_f64(),
+            requests: stats.requests,
+            errors: stats.errors,
+            throughput: latency.throughput(),
+            latency: latency.into(),
+            send_lag: open_loop.then(|| stats.send_lag.summary(duration).into()),
+            errors_by_code: stats.failures.by_code(),
+            error_classes: stats
+                .failures
+                .by_count()
+                .into_iter()
+                .map(|(kind, code, bucket)| ErrorClassReport {
+                    kind: kind.name().to_string(),
+                    code: format!("{:?}", code),
+                    count: bucket.count,
+                    first_seen_secs: bucket.first_seen.as_secs_f64(),
+                    examples: bucket.examples.clone(),
+                })
+                .collect(),
+            streams: streaming.then(|| StreamReport {
+                streams: streams.streams,
+                messages: streams.messages,
+                messages_per_sec: if duration.is_zero() {
+                    0.0
+                } else {
+                    streams.messages as f64 / duration.as_secs_f64()
+                },
+                first_message: streams.first_message.summary(duration).into(),
+                inter_arrival: streams.inter_arrival.summary(duration).into(),
+            }),
+            retries: retrying.then(|| (&stats.retries).into()),
+        }
+    }
+}
+
+/// Latency percentiles in microseconds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LatencyReport {
+    pub count: u64,
+    pub min_us: u64,
+    pub mean_us: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub p999_us: u64,
+    pub max_us: u64,
+}
+
+impl From<LatencySummary> for LatencyReport {
+    fn from(s: LatencySummary) -> Self {
+        let us = |d: Duration| d.as_micros() as u64;
+        Self {
+            count: s.count,
+            min_us: us(s.min),
+            mean_us: s.mean.as_secs_f64() * 1_000_000.0,
+            p50_us: us(s.p50),
+            p90_us: us(s.p90),
+            p99_us: us(s.p99),
+            p999_us: us(s.p999),
+            max_us: us(s.max),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThroughputSample {
+    pub second: usize,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+pub fn throughput_samples(seconds: &[Completions]) -> Vec<ThroughputSample> {
+    seconds
+        .iter()
+        .enumerate()
+        .map(|(second, c)| ThroughputSample {
+            second,
+            requests: c.requests,
+            errors: c.errors,
+        })
+        .collect()
+}
+
+pub fn timestamp(at: SystemTime) -> String {
+    humantime::format_rfc3339_millis(at).to_string()
+}
+
+/// Default result file name for a run that started at the `timestamp()`
+/// `started_at`, safe on every filesystem.
+pub fn result_file_name(started_at: &str, stage: Option<&str>) -> String {
+    let started_at = started_at.replace(':', "-");
+    match stage {
+        Some(stage) => format!("run-{}-{}.json", started_at, file_safe(stage)),
+        None => format!("run-{}.json", started_at),
+    }
+}
+
+/// `path` with `-<stage>` inserted before the extension.
+pub fn stage_path(path: &Path, stage: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, file_safe(stage), ext.to_string_lossy()),
+        None => format!("{}-{}", stem, file_safe(stage)),
+    };
+    path.with_file_name(name)
+}
+
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
+
+impl Report {
+    pub fn write(&self, format: OutputFormat, path: &Path) -> std::io::Result<()> {
+        let contents = match format {
+            OutputFormat::Json => serde_json::to_string_pretty(self).map_err(std::io::Error::other)?,
+            OutputFormat::Csv => self.to_csv(),
+        };
+        std::fs::write(path, contents)
+    }
+
+    /// Long-format CSV, one value per row, so every part of the report fits
+    /// one fixed set of columns.
+    fn to_csv(&self) -> String {
+        let mut rows = vec!["section,name,field,value".to_string()];
+        let mut row = |section: &str, name: &str, field: &str, value: String| {
+            rows.push(format!("{},{},{},{}", section, csv_field(name), field, csv_field(&value)));
+        };
+        let opt = |value: Option<String>| value.unwrap_or_default();
+
+        let c = &self.config;
+        row("config", "", "server", c.server.clone());
+        row("config", "", "rpc", c.rpc.clone());
+        row("config", "", "clients", c.clients.to_string());
+        row("config", "", "connections", c.connections.to_string());
+        row("config", "", "requests", opt(c.requests.map(|r| r.to_string())));
+        row("config", "", "duration_secs", opt(c.duration_secs.map(|d| d.to_string())));
+        row("config", "", "warmup_secs", c.warmup_secs.to_string());
+        row("config", "", "ramp_up_secs", c.ramp_up_secs.to_string());
+        row("config", "", "messages", c.messages.to_string());
+        row("config", "", "rate", opt(c.rate.map(|r| r.to_string())));
+        row("config", "", "tls", c.tls.to_string());
+        row("config", "", "replay", opt(c.replay.clone()));
+        row("config", "", "speed", opt(c.speed.map(|s| s.to_string())));
+        row("config", "", "max_attempts", opt(c.max_attempts.map(|m| m.to_string())));
+        row("config", "", "hedge_after", opt(c.hedge_after.clone()));
+        row("config", "", "hedge_max_fraction", opt(c.hedge_max_fraction.map(|f| f.to_string())));
+        if let Some(stage) = &self.stage {
+            row("stage", "", "scenario", stage.scenario.clone());
+            row("stage", "", "index", stage.index.to_string());
+            row("stage", "", "name", stage.name.clone());
+        }
+
+        row("run", "", "started_at", self.started_at.clone());
+        row("run", "", "finished_at", self.finished_at.clone());
+        row("run", "", "total_duration_secs", self.total_duration_secs.to_string());
+        if let Some(peak) = self.peak_open_streams {
+            row("run", "", "peak_open_streams", peak.to_string());
+        }
+        if let Some(hedging) = &self.hedging {
+            row("run", "", "hedge_calls", hedging.calls.to_string());
+            row("run", "", "hedged", hedging.hedged.to_string());
+            row("run", "", "hedge_wins", hedging.hedge_wins.to_string());
+        }
+
+        for p in &self.phases {
+            let phase = p.phase.as_str();
+            row("phase", phase, "duration_secs", p.duration_secs.to_string());
+            row("phase", phase, "requests", p.requests.to_string());
+            row("phase", phase, "errors", p.errors.to_string());
+            row("phase", phase, "throughput", p.throughput.to_string());
+            if let Some(streams) = &p.streams {
+                row("phase", phase, "streams", streams.streams.to_string());
+                row("phase", phase, "stream_messages", streams.messages.to_string());
+                row("phase", phase, "stream_messages_per_sec", streams.messages_per_sec.to_string());
+            }
+            if let Some(retries) = &p.retries {
+                row("phase", phase, "retried_calls", retries.retried_calls.to_string());
+                row("phase", phase, "retries", retries.retries.to_string());
+                row("phase", phase, "retries_recovered", retries.recovered.to_string());
+                row("phase", phase, "retries_throttled", retries.throttled.to_string());
+                row("phase", phase, "retry_pushbacks", retries.pushbacks.to_string());
+            }
+            let first_message = p.streams.as_ref().map(|s| &s.first_message);
+            let inter_arrival = p.streams.as_ref().map(|s| &s.inter_arrival);
+            for (prefix, latency) in [
+                ("latency", Some(&p.latency)),
+                ("send_lag", p.send_lag.as_ref()),
+                ("first_message", first_message),
+                ("inter_arrival", inter_arrival),
+            ] {
+                let Some(l) = latency else { continue };
+                row("phase", phase, &format!("{}_min_us", prefix), l.min_us.to_string());
+                row("phase", phase, &format!("{}_mean_us", prefix), l.mean_us.to_string());
+                row("phase", phase, &format!("{}_p50_us", prefix), l.p50_us.to_string());
+                row("phase", phase, &format!("{}_p90_us", prefix), l.p90_us.to_string());
+                row("phase", phase, &format!("{}_p99_us", prefix), l.p99_us.to_string());
+                row("phase", phase, &format!("{}_p999_us", prefix), l.p999_us.to_string());
+                row("phase", phase, &format!("{}_max_us", prefix), l.max_us.to_string());
+            }
+            for (code, count) in &p.errors_by_code {
+                row("errors", phase, code, count.to_string());
+            }
+            for class in &p.error_classes {
+                let name = format!("{}/{}/{}", phase, class.kind, class.code);
+                row("error_class", &name, "count", class.count.to_string());
+                row("error_class", &name, "first_seen_secs", class.first_seen_secs.to_string());
+                for (i, example) in class.examples.iter().enumerate() {
+                    row("error_class", &name, &format!("example_{}", i + 1), example.clone());
+                }
+            }
+        }
+
+        for endpoint in self.endpoints.iter().flatten() {
+            row("endpoint", &endpoint.url, "calls", endpoint.calls.to_string());
+            row("endpoint", &endpoint.url, "failures", endpoint.failures.to_string());
+            row("endpoint", &endpoint.url, "ejections", endpoint.ejections.to_string());
+        }
+
+        for sample in &self.throughput {
+            let second = sample.second.to_string();
+            row("throughput", &second, "requests", sample.requests.to_string());
+            row("throughput", &second, "errors", sample.errors.to_string());
+        }
+
+        rows.push(String::new());
+        rows.join("\n")
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        serde_json::from_value(serde_json::json!({
+            "config": {
+                "server": "http://[::1]:50051,http://[::1]:50052",
+                "rpc": "SayHello",
+                "clients": 2,
+                "requests": 10,
+                "duration_secs": null,
+                "warmup_secs": 0.0,
+                "ramp_up_secs": 0.0,
+                "messages": 1,
+                "rate": null,
+                "tls": false,
+            },
+            "started_at": "2024-05-01T12:00:00.000Z",
+            "finished_at": "2024-05-01T12:00:01.000Z",
+            "total_duration_secs": 1.0,
+            "phases": [{
+                "phase": "measure",
+                "duration_secs": 1.0,
+                "requests": 20,
+                "errors": 1,
+                "throughput": 19.0,
+                "latency": {
+                    "count": 19, "min_us": 100, "mean_us": 150.5, "p50_us": 140,
+                    "p90_us": 200, "p99_us": 250, "p999_us": 250, "max_us": 250,
+                },
+                "send_lag": null,
+                "errors_by_code": { "Unavailable": 1 },
+                "error_classes": [{
+                    "kind": "status",
+                    "code": "Unavailable",
+                    "count": 1,
+                    "first_seen_secs": 0.5,
+                    "examples": ["server said \"no\", twice\nthen hung up"],
+                }],
+            }],
+            "throughput": [{ "second": 0, "requests": 20, "errors": 1 }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn quotes_only_fields_that_need_it() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("two\r\nlines"), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn csv_starts_with_the_header_and_ends_with_a_newline() {
+        let csv = report().to_csv();
+        assert!(csv.starts_with("section,name,field,value\n"));
+        assert!(csv.ends_with("throughput,0,errors,1\n"));
+    }
+
+    #[test]
+    fn csv_quotes_values_with_commas_quotes_and_newlines() {
+        let csv = report().to_csv();
+        assert!(csv.contains("\nconfig,,server,\"http://[::1]:50051,http://[::1]:50052\"\n"));
+        assert!(csv.contains(
+            "\nerror_class,measure/status/Unavailable,example_1,\"server said \"\"no\"\", twice\nthen hung up\"\n"
+        ));
+    }
+
+    #[test]
+    fn csv_leaves_missing_options_empty() {
+        let csv = report().to_csv();
+        assert!(csv.contains("\nconfig,,requests,10\n"));
+        assert!(csv.contains("\nconfig,,duration_secs,\n"));
+        assert!(csv.contains("\nconfig,,rate,\n"));
+        assert!(!csv.contains("send_lag_"));
+        assert!(!csv.contains("\nstage,"));
+    }
+
+    #[test]
+    fn csv_has_one_row_per_phase_value() {
+        let csv = report().to_csv();
+        assert!(csv.contains("\nphase,measure,requests,20\n"));
+        assert!(csv.contains("\nphase,measure,latency_mean_us,150.5\n"));
+        assert!(csv.contains("\nerrors,measure,Unavailable,1\n"));
+    }
+
+    #[test]
+    fn result_file_names_avoid_colons_and_odd_stage_names() {
+        assert_eq!(
+            result_file_name("2024-05-01T12:00:00.000Z", None),
+            "run-2024-05-01T12-00-00.000Z.json"
+        );
+        assert_eq!(
+            result_file_name("2024-05-01T12:00:00.000Z", Some("ramp to 1k/s")),
+            "run-2024-05-01T12-00-00.000Z-ramp-to-1k-s.json"
+        );
+    }
+
+    #[test]
+    fn stage_paths_insert_the_stage_before_the_extension() {
+        assert_eq!(stage_path(Path::new("out/report.json"), "warm up"), Path::new("out/report-warm-up.json"));
+        assert_eq!(stage_path(Path::new("report.tar.csv"), "spike_1"), Path::new("report.tar-spike_1.csv"));
+        assert_eq!(stage_path(Path::new("out/report"), "soak"), Path::new("out/report-soak"));
+        assert_eq!(stage_path(Path::new("../a.b/report"), "x/y"), Path::new("../a.b/report-x-y"));
+    }
+}
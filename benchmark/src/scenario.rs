@@ -0,0 +1,473 @@
+//! Scenario files: a sequence of stages, each its own workload, loaded from
+//! TOML so complex load shapes can be checked in and replayed exactly.
+//!
+//! ```toml
+//! name = "spike"
+//! server = "http://[::1]:50051"
+//!
+//! [metadata]
+//! x-benchmark = "spike"
+//!
+//! [[stage]]
+//! name = "baseline"
+//! clients = 10
+//! duration = "30s"
+//!
+//! [[stage]]
+//! name = "burst"
+//! rpc = "server-stream"
+//! clients = 200
+//! connections = 4
+//! rate = 2000
+//! duration = "10s"
+//! payload = { size = 1024 }
+//! retry = { max_attempts = 3, retry_on = ["unavailable", "resource-exhausted"] }
+//!
+//! [[stage]]
+//! name = "hedged"
+//! clients = 10
+//! duration = "30s"
+//! hedge = { after = "p95", max_fraction = 0.05 }
+//! ```
+//!
+//! Stages retry and hedge as the `--max-attempts`, `--retry-*` and
+//! `--hedge-*` flags say unless a `retry` or `hedge` table, for the scenario
+//! or the stage, overrides them. A `server` may list several URLs,
+//! separated by commas, to balance over as `--balance` says.
+
+use crate::replay::{Replay, ReplayError};
+use crate::workload::{metadata_map, Payload, Rpc, Workload, DEFAULT_CLIENTS, DEFAULT_MESSAGES, DEFAULT_REQUESTS};
+use grpc_demo_client::retry::parse_code;
+use grpc_demo_client::{BalanceConfig, HedgeArgs, RetryArgs};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug)]
+pub struct Stage {
+    pub name: String,
+    pub workload: Workload,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    name: Option<String>,
+    /// Target of stages that do not set their own
+    server: Option<String>,
+    /// Sent with every call of every stage
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    /// Default payload of every stage
+    payload: Option<Payload>,
+    /// Default retries of every stage
+    retry: Option<RetryFile>,
+    /// Default hedging of every stage
+    hedge: Option<HedgeFile>,
+    #[serde(rename = "stage", default)]
+    stages: Vec<StageFile>,
+}
+
+/// Mirrors the command-line flags; unset fields take the same defaults.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StageFile {
+    name: String,
+    server: Option<String>,
+    rpc: Option<Rpc>,
+    clients: Option<usize>,
+    connections: Option<usize>,
+    requests: Option<usize>,
+    duration: Option<String>,
+    warmup: Option<String>,
+    ramp_up: Option<String>,
+    messages: Option<usize>,
+    rate: Option<f64>,
+    payload: Option<Payload>,
+    /// Added to, or overriding, the scenario's metadata
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    /// Recorded traffic to send instead, relative to the scenario file
+    replay: Option<PathBuf>,
+    speed: Option<f64>,
+    /// Overrides the scenario's retry fields one by one
+    retry: Option<RetryFile>,
+    /// Overrides the scenario's hedge fields one by one
+    hedge: Option<HedgeFile>,
+}
+
+/// Mirrors the retry flags; unset fields keep the command line's values.
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct RetryFile {
+    max_attempts: Option<u32>,
+    retry_on: Option<Vec<String>>,
+    backoff: Option<String>,
+    max_backoff: Option<String>,
+    budget: Option<u32>,
+}
+
+impl RetryFile {
+    /// `args` with this table's fields applied.
+    fn apply(&self, args: &RetryArgs) -> Result<RetryArgs, String> {
+        let mut args = args.clone();
+        if let Some(max_attempts) = self.max_attempts {
+            if max_attempts == 0 {
+                return Err("retry max_attempts must be at least 1".to_string());
+            }
+            args.max_attempts = max_attempts;
+        }
+        if let Some(codes) = &self.retry_on {
+            args.retry_on = codes.iter().map(|code| parse_code(code)).collect::<Result<_, _>>()?;
+        }
+        if let Some(backoff) = &self.backoff {
+            args.retry_backoff = crate::parse_duration(backoff)?;
+        }
+        if let Some(max_backoff) = &self.max_backoff {
+            args.retry_max_backoff = crate::parse_duration(max_backoff)?;
+        }
+        if let Some(budget) = self.budget {
+            args.retry_budget = budget;
+        }
+        Ok(args)
+    }
+}
+
+/// Mirrors the hedge flags; unset fields keep the command line's values.
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct HedgeFile {
+    /// `20ms` or `p95`; `off` turns hedging off
+    after: Option<String>,
+    max_fraction: Option<f64>,
+}
+
+impl HedgeFile {
+    /// `args` with this table's fields applied.
+    fn apply(&self, args: &HedgeArgs) -> Result<HedgeArgs, String> {
+        let mut args = args.clone();
+        match self.after.as_deref() {
+            Some("off") => args.hedge_after = None,
+            Some(after) => args.hedge_after = Some(after.parse()?),
+            None => {}
+        }
+        if let Some(max_fraction) = self.max_fraction {
+            if !(0.0..=1.0).contains(&max_fraction) {
+                return Err(format!("hedge max_fraction {} is not between 0 and 1", max_fraction));
+            }
+            args.hedge_max_fraction = max_fraction;
+        }
+        Ok(args)
+    }
+}
+
+/// What stages use where the file is silent: the command line's settings.
+pub struct Defaults<'a> {
+    pub server: &'a str,
+    /// Replaces `server` when set.
+    pub endpoints_file: Option<&'a Path>,
+    pub balance: BalanceConfig,
+    pub retry: &'a RetryArgs,
+    pub hedge: &'a HedgeArgs,
+}
+
+impl Scenario {
+    /// Reads a scenario, filling in what it leaves out from `defaults`.
+    pub fn load(path: &Path, defaults: &Defaults) -> Result<Self, ScenarioError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ScenarioError::Read(path.to_path_buf(), e))?;
+        let file: ScenarioFile = toml::from_str(&text).map_err(|e| ScenarioError::Parse(path.to_path_buf(), e))?;
+        if file.stages.is_empty() {
+            return Err(ScenarioError::Invalid("no [[stage]] defined".to_string()));
+        }
+
+        let mut names = HashSet::new();
+        let mut stages = Vec::new();
+        for stage in file.stages {
+            let invalid = |msg: String| ScenarioError::Invalid(format!("stage '{}': {}", stage.name, msg));
+            if stage.name.is_empty() {
+                return Err(ScenarioError::Invalid("every stage needs a name".to_string()));
+            }
+            if !names.insert(stage.name.clone()) {
+                return Err(invalid("duplicate stage name".to_string()));
+            }
+            if stage.requests.is_some() && stage.duration.is_some() {
+                return Err(invalid("set either requests or duration, not both".to_string()));
+            }
+            let clients = stage.clients.unwrap_or(DEFAULT_CLIENTS);
+            if clients == 0 {
+                return Err(invalid("clients must be at least 1".to_string()));
+            }
+            if stage.connections == Some(0) {
+                return Err(invalid("connections must be at least 1".to_string()));
+            }
+            if let Some(rate) = stage.rate {
+                if !rate.is_finite() || rate <= 0.0 {
+                    return Err(invalid(format!("rate {} is not a positive number of requests per second", rate)));
+                }
+            }
+            if stage.replay.is_some() {
+                let generated = [
+                    ("rpc", stage.rpc.is_some()),
+                    ("requests", stage.requests.is_some()),
+                    ("duration", stage.duration.is_some()),
+                    ("warmup", stage.warmup.is_some()),
+                    ("ramp_up", stage.ramp_up.is_some()),
+                    ("messages", stage.messages.is_some()),
+                    ("rate", stage.rate.is_some()),
+                    ("payload", stage.payload.is_some()),
+                ];
+                if let Some((key, _)) = generated.iter().find(|(_, set)| *set) {
+                    return Err(invalid(format!("{} cannot be combined with replay", key)));
+                }
+            } else if stage.speed.is_some() {
+                return Err(invalid("speed only applies to replay".to_string()));
+            }
+            let speed = stage.speed.unwrap_or(1.0);
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(invalid(format!("speed {} is not a positive factor", speed)));
+            }
+            let replay = match &stage.replay {
+                Some(file) => {
+                    let file = path.parent().unwrap_or(Path::new("")).join(file);
+                    Some(Arc::new(Replay::load(&file, speed).map_err(ScenarioError::Replay)?))
+                }
+                None => None,
+            };
+            let duration = |value: Option<&str>| value.map(crate::parse_duration).transpose().map_err(invalid);
+
+            let mut metadata = file.metadata.clone();
+            metadata.extend(stage.metadata.clone());
+
+            let mut retry = defaults.retry.clone();
+            for table in [&file.retry, &stage.retry].into_iter().flatten() {
+                retry = table.apply(&retry).map_err(invalid)?;
+            }
+            let mut hedge = defaults.hedge.clone();
+            for table in [&file.hedge, &stage.hedge].into_iter().flatten() {
+                hedge = table.apply(&hedge).map_err(invalid)?;
+            }
+            let server = stage.server.clone().or_else(|| file.server.clone());
+            if server.as_deref().is_some_and(|server| server.split(',').all(|url| url.trim().is_empty())) {
+                return Err(invalid("server lists no URL".to_string()));
+            }
+            // Recorded traffic may mix RPCs; only its unary calls are hedged
+            if hedge.hedge_after.is_some() && stage.replay.is_none() && !matches!(stage.rpc, None | Some(Rpc::Unary)) {
+                return Err(invalid("hedging only applies to unary calls".to_string()));
+            }
+
+            stages.push(Stage {
+                workload: Workload {
+                    server: server.clone().unwrap_or_else(|| defaults.server.to_string()),
+                    endpoints_file: match server {
+                        Some(_) => None,
+                        None => defaults.endpoints_file.map(Path::to_path_buf),
+                    },
+                    balance: defaults.balance,
+                    rpc: stage.rpc.unwrap_or_default(),
+                    clients,
+                    connections: Workload::connections_for(clients, stage.connections),
+                    requests: stage.requests.unwrap_or(DEFAULT_REQUESTS),
+                    duration: duration(stage.duration.as_deref())?,
+                    warmup: duration(stage.warmup.as_deref())?.unwrap_or_default(),
+                    ramp_up: duration(stage.ramp_up.as_deref())?.unwrap_or_default(),
+                    messages: stage.messages.unwrap_or(DEFAULT_MESSAGES),
+                    rate: stage.rate,
+                    payload: stage.payload.clone().or_else(|| file.payload.clone()).unwrap_or_default(),
+                    metadata: metadata_map(&metadata).map_err(invalid)?,
+                    retry: retry.policy(),
+                    hedge: hedge.policy(),
+                    replay,
+                },
+                name: stage.name,
+            });
+        }
+
+        let name = file.name.unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        Ok(Scenario { name, stages })
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+    Replay(ReplayError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ScenarioError::Parse(path, e) => write!(f, "invalid scenario file {}: {}", path.display(), e),
+            ScenarioError::Invalid(msg) => write!(f, "invalid scenario: {}", msg),
+            ScenarioError::Replay(e) => write!(f, "invalid scenario: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScenarioError::Read(_, e) => Some(e),
+            ScenarioError::Parse(_, e) => Some(e),
+            ScenarioError::Invalid(_) => None,
+            ScenarioError::Replay(e) => e.source(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::time::Duration;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("grpc-demo-scenario-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Loads `path` with the command line's default flags.
+    fn load_path(path: &Path) -> Result<Scenario, ScenarioError> {
+        let args = crate::Args::parse_from(["grpc-demo-benchmark"]);
+        let defaults = Defaults {
+            server: "http://[::1]:50051",
+            endpoints_file: None,
+            balance: BalanceConfig::default(),
+            retry: &args.retry,
+            hedge: &args.hedge,
+        };
+        Scenario::load(path, &defaults)
+    }
+
+    fn load(name: &str, contents: &str) -> Result<Scenario, ScenarioError> {
+        let path = temp_file(name, contents);
+        let result = load_path(&path);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    /// The `Invalid` message of a scenario that must be rejected.
+    fn invalid(name: &str, contents: &str) -> String {
+        match load(name, contents) {
+            Err(ScenarioError::Invalid(msg)) => msg,
+            other => panic!("expected an invalid scenario, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stages_inherit_the_scenario_and_command_line_defaults() {
+        let scenario = load(
+            "inherit",
+            r#"
+            server = "http://[::1]:50052"
+            metadata = { x-run = "nightly", x-stage = "none" }
+
+            [[stage]]
+            name = "baseline"
+            clients = 4
+            duration = "30s"
+            warmup = "500ms"
+
+            [[stage]]
+            name = "elsewhere"
+            server = "http://[::1]:50053"
+            metadata = { x-stage = "two" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.name, format!("grpc-demo-scenario-{}-inherit", std::process::id()));
+        let [baseline, elsewhere] = &scenario.stages[..] else { panic!("expected two stages") };
+        assert_eq!(baseline.workload.server, "http://[::1]:50052");
+        assert_eq!(baseline.workload.clients, 4);
+        assert_eq!(baseline.workload.duration, Some(Duration::from_secs(30)));
+        assert_eq!(baseline.workload.warmup, Duration::from_millis(500));
+        assert_eq!(elsewhere.workload.server, "http://[::1]:50053");
+        assert_eq!(elsewhere.workload.clients, DEFAULT_CLIENTS);
+        assert_eq!(elsewhere.workload.requests, DEFAULT_REQUESTS);
+        assert_eq!(elsewhere.workload.duration, None);
+        assert_eq!(elsewhere.workload.metadata.get("x-run").unwrap(), "nightly");
+        assert_eq!(elsewhere.workload.metadata.get("x-stage").unwrap(), "two");
+    }
+
+    #[test]
+    fn a_scenario_needs_named_distinct_stages() {
+        assert_eq!(invalid("empty", "name = \"nothing\""), "no [[stage]] defined");
+        assert_eq!(invalid("unnamed", "[[stage]]\nname = \"\""), "every stage needs a name");
+        assert_eq!(
+            invalid("twice", "[[stage]]\nname = \"a\"\n[[stage]]\nname = \"a\""),
+            "stage 'a': duplicate stage name"
+        );
+    }
+
+    #[test]
+    fn rejects_stages_with_impossible_sizes() {
+        let stage = |body: &str| format!("[[stage]]\nname = \"s\"\n{}", body);
+        let cases = [
+            ("both", "requests = 10\nduration = \"1s\"", "set either requests or duration, not both"),
+            ("clients", "clients = 0", "clients must be at least 1"),
+            ("connections", "connections = 0", "connections must be at least 1"),
+            ("rate", "rate = 0.0", "rate 0 is not a positive number of requests per second"),
+            ("nan", "rate = nan", "rate NaN is not a positive number of requests per second"),
+            ("speed", "speed = 2.0", "speed only applies to replay"),
+            ("server", "server = \" , \"", "server lists no URL"),
+        ];
+        for (name, body, expected) in cases {
+            assert_eq!(invalid(name, &stage(body)), format!("stage 's': {}", expected));
+        }
+    }
+
+    #[test]
+    fn rejects_durations_that_do_not_parse() {
+        for (name, value) in [("word", "soon"), ("negative", "-1s"), ("huge", "1e300s")] {
+            let msg = invalid(name, &format!("[[stage]]\nname = \"s\"\nduration = \"{}\"", value));
+            assert!(msg.starts_with("stage 's': ") && msg.contains("is not a duration"), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn replay_stages_cannot_also_generate_calls() {
+        let msg = invalid("replay", "[[stage]]\nname = \"s\"\nreplay = \"calls.jsonl\"\nrpc = \"chat\"");
+        assert_eq!(msg, "stage 's': rpc cannot be combined with replay");
+    }
+
+    #[test]
+    fn rejects_bad_retry_and_hedge_tables() {
+        let msg = invalid("attempts", "retry = { max_attempts = 0 }\n[[stage]]\nname = \"s\"");
+        assert_eq!(msg, "stage 's': retry max_attempts must be at least 1");
+        let msg = invalid("fraction", "[[stage]]\nname = \"s\"\nhedge = { after = \"p95\", max_fraction = 1.5 }");
+        assert_eq!(msg, "stage 's': hedge max_fraction 1.5 is not between 0 and 1");
+        let msg = invalid("streaming", "[[stage]]\nname = \"s\"\nrpc = \"chat\"\nhedge = { after = \"20ms\" }");
+        assert_eq!(msg, "stage 's': hedging only applies to unary calls");
+    }
+
+    #[test]
+    fn rejects_metadata_that_cannot_be_sent() {
+        let msg = invalid("metadata", "[[stage]]\nname = \"s\"\nmetadata = { \"bad key\" = \"v\" }");
+        assert_eq!(msg, "stage 's': 'bad key' is not a valid ASCII metadata key");
+    }
+
+    #[test]
+    fn unknown_keys_and_missing_files_are_reported() {
+        assert!(matches!(
+            load("typo", "[[stage]]\nname = \"s\"\nclient = 3"),
+            Err(ScenarioError::Parse(..))
+        ));
+        let missing = load_path(Path::new("/nonexistent/scenario.toml"));
+        assert!(matches!(missing, Err(ScenarioError::Read(..))));
+    }
+}
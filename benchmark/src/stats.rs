@@ -0,0 +1,349 @@
+//! Per-request latency and throughput recording, and the summary printed
+//! after a run.
+
+use grpc_demo_client::RetryStats;
+use hdrhistogram::Histogram;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Highest latency the histograms track precisely; slower calls are clamped.
+const MAX_LATENCY_MICROS: u64 = 60 * 60 * 1_000_000;
+
+/// HDR histogram of call latencies in microseconds, 3 significant digits.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            histogram: Histogram::new_with_bounds(1, MAX_LATENCY_MICROS, 3).expect("valid histogram bounds"),
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.histogram.saturating_record(micros.max(1));
+    }
+
+    /// Adds every sample of `other`, e.g. to combine per-client histograms.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        self.histogram
+            .add(&other.histogram)
+            .expect("histograms share the same bounds");
+    }
+
+    /// Percentiles over the recorded calls, with throughput over `elapsed`.
+    pub fn summary(&self, elapsed: Duration) -> LatencySummary {
+        let h = &self.histogram;
+        let micros = Duration::from_micros;
+        let empty = h.is_empty();
+        LatencySummary {
+            count: h.len(),
+            elapsed,
+            min: if empty { Duration::ZERO } else { micros(h.min()) },
+            mean: if empty { Duration::ZERO } else { Duration::from_secs_f64(h.mean() / 1_000_000.0) },
+            p50: micros(h.value_at_quantile(0.50)),
+            p90: micros(h.value_at_quantile(0.90)),
+            p99: micros(h.value_at_quantile(0.99)),
+            p999: micros(h.value_at_quantile(0.999)),
+            max: micros(h.max()),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Latency percentiles and throughput of a set of calls.
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub elapsed: Duration,
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// Calls completed per second of wall-clock time.
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            0.0
+        } else {
+            self.count as f64 / self.elapsed.as_secs_f64()
+        }
+    }
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Successful calls: {}", self.count)?;
+        writeln!(f, "Throughput: {:.1} req/s", self.throughput())?;
+        writeln!(f, "Latency min: {:?}", self.min)?;
+        writeln!(f, "Latency mean: {:?}", self.mean)?;
+        writeln!(f, "Latency p50: {:?}", self.p50)?;
+        writeln!(f, "Latency p90: {:?}", self.p90)?;
+        writeln!(f, "Latency p99: {:?}", self.p99)?;
+        writeln!(f, "Latency p99.9: {:?}", self.p999)?;
+        write!(f, "Latency max: {:?}", self.max)
+    }
+}
+
+/// Calls completed during one second of the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Completions {
+    pub requests: u64,
+    pub errors: u64,
+}
+
+/// Completed calls per second since the start of the run, across all phases.
+#[derive(Debug, Clone, Default)]
+pub struct ThroughputSeries {
+    seconds: Vec<Completions>,
+}
+
+impl ThroughputSeries {
+    pub fn record(&mut self, second: usize, success: bool) {
+        if self.seconds.len() <= second {
+            self.seconds.resize(second + 1, Completions::default());
+        }
+        let bucket = &mut self.seconds[second];
+        bucket.requests += 1;
+        if !success {
+            bucket.errors += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &ThroughputSeries) {
+        for (second, completions) in other.seconds.iter().enumerate() {
+            if self.seconds.len() <= second {
+                self.seconds.resize(second + 1, Completions::default());
+            }
+            self.seconds[second].requests += completions.requests;
+            self.seconds[second].errors += completions.errors;
+        }
+    }
+
+    /// One entry per second, starting at the beginning of the run.
+    pub fn seconds(&self) -> &[Completions] {
+        &self.seconds
+    }
+}
+
+static OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+static PEAK_OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a response stream as open until dropped, tracking the peak.
+pub struct OpenStream(());
+
+impl OpenStream {
+    pub fn open() -> Self {
+        let open = OPEN_STREAMS.fetch_add(1, Ordering::Relaxed) + 1;
+        PEAK_OPEN_STREAMS.fetch_max(open, Ordering::Relaxed);
+        OpenStream(())
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        OPEN_STREAMS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Most response streams open at the same time since the process started
+/// or the last `reset_peak_open_streams()`.
+pub fn peak_open_streams() -> usize {
+    PEAK_OPEN_STREAMS.load(Ordering::Relaxed)
+}
+
+/// Starts tracking the peak afresh, e.g. between scenario stages.
+pub fn reset_peak_open_streams() {
+    PEAK_OPEN_STREAMS.store(OPEN_STREAMS.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// When the messages of one response stream arrived.
+#[derive(Debug, Default)]
+pub struct StreamTimings {
+    /// From sending the call to the first response message
+    pub first_message: Option<Duration>,
+    /// Gaps between consecutive response messages
+    pub inter_arrival: Vec<Duration>,
+}
+
+/// Response-stream timings of many calls.
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    pub streams: u64,
+    pub messages: u64,
+    pub first_message: LatencyHistogram,
+    pub inter_arrival: LatencyHistogram,
+}
+
+impl StreamStats {
+    pub fn record(&mut self, timings: &StreamTimings) {
+        self.streams += 1;
+        if let Some(first) = timings.first_message {
+            self.messages += 1;
+            self.first_message.record(first);
+        }
+        self.messages += timings.inter_arrival.len() as u64;
+        for gap in &timings.inter_arrival {
+            self.inter_arrival.record(*gap);
+        }
+    }
+
+    pub fn merge(&mut self, other: &StreamStats) {
+        self.streams += other.streams;
+        self.messages += other.messages;
+        self.first_message.merge(&other.first_message);
+        self.inter_arrival.merge(&other.inter_arrival);
+    }
+}
+
+/// What retrying did across many calls.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RetryTally {
+    /// Calls that took more than one attempt
+    pub retried_calls: u64,
+    /// Attempts after the first
+    pub retries: u64,
+    /// Retried calls that then succeeded
+    pub recovered: u64,
+    /// Calls the retry budget kept from retrying
+    pub throttled: u64,
+    /// Retries that waited as long as the server asked
+    pub pushbacks: u64,
+}
+
+impl RetryTally {
+    pub fn record(&mut self, stats: &RetryStats, succeeded: bool) {
+        if stats.retries() > 0 {
+            self.retried_calls += 1;
+            self.retries += stats.retries() as u64;
+            if succeeded {
+                self.recovered += 1;
+            }
+        }
+        self.throttled += stats.throttled as u64;
+        self.pushbacks += stats.pushbacks as u64;
+    }
+
+    pub fn merge(&mut self, other: &RetryTally) {
+        self.retried_calls += other.retried_calls;
+        self.retries += other.retries;
+        self.recovered += other.recovered;
+        self.throttled += other.throttled;
+        self.pushbacks += other.pushbacks;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(values: impl IntoIterator<Item = u64>) -> LatencyHistogram {
+        let mut histogram = LatencyHistogram::new();
+        for value in values {
+            histogram.record(Duration::from_micros(value));
+        }
+        histogram
+    }
+
+    #[test]
+    fn an_empty_histogram_summarizes_to_zero() {
+        let summary = LatencyHistogram::new().summary(Duration::from_secs(1));
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.min, Duration::ZERO);
+        assert_eq!(summary.mean, Duration::ZERO);
+        assert_eq!(summary.p50, Duration::ZERO);
+        assert_eq!(summary.max, Duration::ZERO);
+        assert_eq!(summary.throughput(), 0.0);
+    }
+
+    #[test]
+    fn summarizes_percentiles_and_throughput() {
+        let summary = micros(1..=1000).summary(Duration::from_secs(2));
+        assert_eq!(summary.count, 1000);
+        assert_eq!(summary.min, Duration::from_micros(1));
+        assert_eq!(summary.mean, Duration::from_nanos(500_500));
+        assert_eq!(summary.p50, Duration::from_micros(500));
+        assert_eq!(summary.p90, Duration::from_micros(900));
+        assert_eq!(summary.p99, Duration::from_micros(990));
+        assert_eq!(summary.p999, Duration::from_micros(999));
+        assert_eq!(summary.max, Duration::from_micros(1000));
+        assert_eq!(summary.throughput(), 500.0);
+    }
+
+    #[test]
+    fn throughput_over_no_time_is_zero() {
+        assert_eq!(micros([5]).summary(Duration::ZERO).throughput(), 0.0);
+    }
+
+    #[test]
+    fn merging_combines_the_samples_of_both() {
+        let mut merged = micros(1..=500);
+        merged.merge(&micros(501..=1000));
+        let summary = merged.summary(Duration::from_secs(1));
+        assert_eq!(summary.count, 1000);
+        assert_eq!(summary.min, Duration::from_micros(1));
+        assert_eq!(summary.p50, Duration::from_micros(500));
+        assert_eq!(summary.max, Duration::from_micros(1000));
+    }
+
+    #[test]
+    fn merging_an_empty_histogram_changes_nothing() {
+        let mut merged = micros([10, 20, 30]);
+        merged.merge(&LatencyHistogram::new());
+        let summary = merged.summary(Duration::from_secs(1));
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.min, Duration::from_micros(10));
+        assert_eq!(summary.max, Duration::from_micros(30));
+    }
+
+    #[test]
+    fn sub_microsecond_calls_count_as_one_microsecond() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(Duration::ZERO);
+        histogram.record(Duration::from_nanos(300));
+        let summary = histogram.summary(Duration::from_secs(1));
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.max, Duration::from_micros(1));
+    }
+
+    #[test]
+    fn calls_slower_than_the_bound_are_clamped() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(Duration::from_secs(10 * 3600));
+        histogram.record(Duration::MAX);
+        let summary = histogram.summary(Duration::from_secs(1));
+        assert_eq!(summary.count, 2);
+        let bound = Duration::from_micros(MAX_LATENCY_MICROS);
+        assert!(summary.max >= bound.mul_f64(0.999) && summary.max <= bound.mul_f64(1.001));
+    }
+
+    #[test]
+    fn throughput_series_merge_by_second() {
+        let mut a = ThroughputSeries::default();
+        a.record(0, true);
+        a.record(2, false);
+        let mut b = ThroughputSeries::default();
+        b.record(0, false);
+        b.record(3, true);
+        a.merge(&b);
+        let completions = |requests, errors| Completions { requests, errors };
+        assert_eq!(
+            a.seconds(),
+            [completions(2, 1), completions(0, 0), completions(1, 1), completions(1, 0)]
+        );
+    }
+}
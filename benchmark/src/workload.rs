@@ -0,0 +1,184 @@
+//! What one benchmark run sends: built from the command line, or from each
+//! stage of a scenario file.
+
+use crate::replay::Replay;
+use grpc_demo_client::balance::{read_endpoints, split_urls};
+use grpc_demo_client::{BalanceConfig, HedgePolicy, RetryPolicy};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+
+pub const DEFAULT_CLIENTS: usize = 10;
+pub const DEFAULT_REQUESTS: usize = 100;
+pub const DEFAULT_MESSAGES: usize = 10;
+
+/// Which RPC each benchmark request exercises
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rpc {
+    /// SayHello
+    #[default]
+    Unary,
+    /// SayHelloStream, timing every message the server streams back
+    ServerStream,
+    /// SayHelloClientStream, sending `--messages` requests per call
+    ClientStream,
+    /// SayHelloChat, exchanging `--messages` request/reply pairs per call
+    Chat,
+}
+
+impl Rpc {
+    /// Whether the server streams replies back.
+    pub fn streams_replies(&self) -> bool {
+        matches!(self, Rpc::ServerStream | Rpc::Chat)
+    }
+
+    pub fn name(&self) -> String {
+        self.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+    }
+}
+
+/// How the `name` of each request is generated.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Payload {
+    /// `{client}` and `{request}` are replaced by the client and request numbers.
+    #[serde(default = "default_template")]
+    pub template: String,
+    /// Pad names shorter than this many bytes, to test larger messages.
+    #[serde(default)]
+    pub size: usize,
+}
+
+fn default_template() -> String {
+    "Client-{client}-Request-{request}".to_string()
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self {
+            template: default_template(),
+            size: 0,
+        }
+    }
+}
+
+impl Payload {
+    pub fn name(&self, client_id: usize, request: usize) -> String {
+        let mut name = self
+            .template
+            .replace("{client}", &client_id.to_string())
+            .replace("{request}", &request.to_string());
+        if name.len() < self.size {
+            name.extend(std::iter::repeat_n('.', self.size - name.len()));
+        }
+        name
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Workload {
+    /// One URL, or a comma-separated list to balance over.
+    pub server: String,
+    /// Read the servers from this file instead, and follow its changes.
+    pub endpoints_file: Option<PathBuf>,
+    /// How calls are spread over several servers.
+    pub balance: BalanceConfig,
+    pub rpc: Rpc,
+    pub clients: usize,
+    /// Connections the clients share round-robin, between 1 and `clients`.
+    pub connections: usize,
+    /// Measured calls per client when `duration` is `None`.
+    pub requests: usize,
+    pub duration: Option<Duration>,
+    pub warmup: Duration,
+    pub ramp_up: Duration,
+    /// Messages sent per call for the client-streaming RPCs.
+    pub messages: usize,
+    /// Open-loop rate in calls per second across all clients.
+    pub rate: Option<f64>,
+    pub payload: Payload,
+    /// Sent with every call.
+    pub metadata: MetadataMap,
+    /// How failed calls are retried; its budget is shared by every client.
+    pub retry: RetryPolicy,
+    /// Hedges unary calls; the hedge cap and counts cover every client.
+    pub hedge: Option<HedgePolicy>,
+    /// Send these recorded calls instead; `clients` then caps how many are
+    /// in flight at once.
+    pub replay: Option<Arc<Replay>>,
+}
+
+impl Workload {
+    /// Clamps a requested connection count to `1..=clients`; `None` gives
+    /// each client its own connection.
+    pub fn connections_for(clients: usize, connections: Option<usize>) -> usize {
+        connections.unwrap_or(clients).clamp(1, clients.max(1))
+    }
+
+    /// The servers to balance over.
+    pub fn endpoint_urls(&self) -> Result<Vec<String>, String> {
+        match &self.endpoints_file {
+            Some(path) => read_endpoints(path),
+            None => match split_urls(&self.server) {
+                urls if urls.is_empty() => Err("no server to send to".to_string()),
+                urls => Ok(urls),
+            },
+        }
+    }
+
+    /// The servers as shown in results: the list, or the endpoints file.
+    pub fn target(&self) -> String {
+        match &self.endpoints_file {
+            Some(path) => path.display().to_string(),
+            None => self.server.clone(),
+        }
+    }
+
+    /// Whether any call has the server stream replies back.
+    pub fn streams_replies(&self) -> bool {
+        match &self.replay {
+            Some(replay) => replay.streams_replies(),
+            None => self.rpc.streams_replies(),
+        }
+    }
+
+    /// Whether calls go out on a timetable rather than after the previous reply.
+    pub fn open_loop(&self) -> bool {
+        self.rate.is_some() || self.replay.is_some()
+    }
+
+    /// `rpc`, or `replay` for recorded traffic, which may mix RPCs.
+    pub fn rpc_name(&self) -> String {
+        match self.replay {
+            Some(_) => "replay".to_string(),
+            None => self.rpc.name(),
+        }
+    }
+}
+
+/// Wraps a message in a request carrying `metadata`.
+pub fn request<T>(message: T, metadata: &MetadataMap) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    *request.metadata_mut() = metadata.clone();
+    request
+}
+
+/// Validates `key = value` pairs from a scenario or recording as ASCII metadata.
+pub fn metadata_map(entries: &BTreeMap<String, String>) -> Result<MetadataMap, String> {
+    let mut metadata = MetadataMap::new();
+    for (key, value) in entries {
+        let key: AsciiMetadataKey = key
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid ASCII metadata key", key))?;
+        let value: AsciiMetadataValue = value
+            .parse()
+            .map_err(|_| format!("metadata '{}' has a value that is not printable ASCII", key))?;
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
+}
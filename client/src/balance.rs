@@ -0,0 +1,606 @@
+//! Client-side load balancing over several server endpoints.
+//!
+//! A `Balancer` holds one `Channel` per endpoint and connection. Each
+//! connection is served by a `BalancedChannel`, which picks an endpoint per
+//! call by round-robin, least outstanding requests or power of two choices.
+//! Outstanding calls, failures and ejections are shared across connections.
+//! An endpoint whose calls fail `eject_after` times in a row gets no calls
+//! for `eject_for`. If every endpoint is ejected, calls go to all of them
+//! rather than nowhere.
+//!
+//! The endpoint list can come from a file, re-read whenever it changes.
+//!
+//! ```ignore
+//! let balancer = Balancer::new(BalanceConfig::default());
+//! balancer.insert(url, vec![endpoint.connect().await?]);
+//! let mut client = GreeterServiceClient::new(balancer.connection(0));
+//! ```
+
+use clap::{Args, ValueEnum};
+use rand::Rng;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tonic::body::BoxBody;
+use tonic::transport::Channel;
+use tonic::Status;
+use tower::{Service, ServiceExt};
+use tracing::{info, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How often a watched endpoints file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How each call picks an endpoint
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BalancePolicy {
+    /// Each endpoint in turn
+    #[default]
+    RoundRobin,
+    /// The endpoint with the fewest calls in flight
+    LeastOutstanding,
+    /// The less busy of two endpoints picked at random
+    #[value(name = "p2c")]
+    PowerOfTwoChoices,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BalanceConfig {
+    pub policy: BalancePolicy,
+    /// Consecutive failed calls that eject an endpoint; 0 never ejects.
+    pub eject_after: u32,
+    pub eject_for: Duration,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        Self {
+            policy: BalancePolicy::default(),
+            eject_after: 5,
+            eject_for: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Counts for one endpoint since it was added.
+#[derive(Debug, Clone)]
+pub struct EndpointStats {
+    pub url: String,
+    pub calls: u64,
+    /// Calls that failed in transport or with `UNAVAILABLE`.
+    pub failures: u64,
+    pub ejections: u64,
+    pub ejected: bool,
+}
+
+#[derive(Debug)]
+struct Backend {
+    url: String,
+    /// One per connection
+    channels: Vec<Channel>,
+    outstanding: AtomicUsize,
+    consecutive_failures: AtomicU32,
+    /// Microseconds after `Shared::epoch`; 0 when not ejected.
+    ejected_until: AtomicU64,
+    calls: AtomicU64,
+    failures: AtomicU64,
+    ejections: AtomicU64,
+}
+
+impl Backend {
+    fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: BalanceConfig,
+    epoch: Instant,
+    backends: RwLock<Arc<Vec<Arc<Backend>>>>,
+    next: AtomicUsize,
+}
+
+impl Shared {
+    fn now_micros(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Chooses an endpoint without allocating; only the chosen one is cloned.
+    fn pick(&self) -> Option<Arc<Backend>> {
+        let backends = self.backends.read().unwrap();
+        let now = self.now_micros();
+        let healthy = backends
+            .iter()
+            .filter(|b| b.ejected_until.load(Ordering::Relaxed) <= now)
+            .count();
+        // With every endpoint ejected, calls go to all of them
+        let all = healthy == 0;
+        let len = if all { backends.len() } else { healthy };
+        if len == 0 {
+            return None;
+        }
+        let candidates = || {
+            backends
+                .iter()
+                .filter(move |b| all || b.ejected_until.load(Ordering::Relaxed) <= now)
+        };
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let picked = match self.config.policy {
+            BalancePolicy::RoundRobin => candidates().nth(start % len),
+            // Starting from a rotating index spreads ties evenly
+            BalancePolicy::LeastOutstanding => candidates()
+                .cycle()
+                .skip(start % len)
+                .take(len)
+                .min_by_key(|b| b.outstanding()),
+            BalancePolicy::PowerOfTwoChoices if len == 1 => candidates().next(),
+            BalancePolicy::PowerOfTwoChoices => {
+                let mut rng = rand::thread_rng();
+                let first = rng.gen_range(0..len);
+                let mut second = rng.gen_range(0..len - 1);
+                if second >= first {
+                    second += 1;
+                }
+                match (candidates().nth(first), candidates().nth(second)) {
+                    (Some(a), Some(b)) => Some(if b.outstanding() < a.outstanding() { b } else { a }),
+                    (a, b) => a.or(b),
+                }
+            }
+        };
+        // An endpoint ejected by another call since counting can leave no candidate
+        picked.or_else(|| backends.first()).cloned()
+    }
+
+    fn record(&self, backend: &Backend, ok: bool) {
+        if ok {
+            backend.consecutive_failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        backend.failures.fetch_add(1, Ordering::Relaxed);
+        let failures = backend.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let eject_after = self.config.eject_after;
+        if eject_after > 0 && failures >= eject_after {
+            backend.consecutive_failures.store(0, Ordering::Relaxed);
+            let until = self.now_micros() + self.config.eject_for.as_micros() as u64;
+            backend.ejected_until.store(until, Ordering::Relaxed);
+            backend.ejections.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Ejecting {} for {:?} after {} failed calls in a row",
+                backend.url, self.config.eject_for, failures
+            );
+        }
+    }
+}
+
+/// Endpoints and their health, shared by every `BalancedChannel` made from
+/// it. Clones share the same endpoints.
+#[derive(Debug, Clone)]
+pub struct Balancer {
+    shared: Arc<Shared>,
+}
+
+impl Balancer {
+    pub fn new(config: BalanceConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                epoch: Instant::now(),
+                backends: RwLock::default(),
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Adds `url` with one channel per connection, replacing any endpoint
+    /// with the same URL.
+    pub fn insert(&self, url: String, channels: Vec<Channel>) {
+        assert!(!channels.is_empty(), "an endpoint needs at least one channel");
+        let backend = Arc::new(Backend {
+            url,
+            channels,
+            outstanding: AtomicUsize::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            ejected_until: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            ejections: AtomicU64::new(0),
+        });
+        let mut backends = self.shared.backends.write().unwrap();
+        let mut updated: Vec<_> = backends.iter().filter(|b| b.url != backend.url).cloned().collect();
+        updated.push(backend);
+        *backends = Arc::new(updated);
+    }
+
+    /// Drops the endpoints not in `urls`; their calls in flight finish.
+    pub fn retain(&self, urls: &[String]) {
+        let mut backends = self.shared.backends.write().unwrap();
+        *backends = Arc::new(backends.iter().filter(|b| urls.contains(&b.url)).cloned().collect());
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        self.shared.backends.read().unwrap().iter().map(|b| b.url.clone()).collect()
+    }
+
+    /// The service for connection `index`, for `GreeterServiceClient::new`.
+    pub fn connection(&self, index: usize) -> BalancedChannel {
+        BalancedChannel {
+            shared: self.shared.clone(),
+            connection: index,
+        }
+    }
+
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        let now = self.shared.now_micros();
+        self.shared
+            .backends
+            .read()
+            .unwrap()
+            .iter()
+            .map(|b| EndpointStats {
+                url: b.url.clone(),
+                calls: b.calls.load(Ordering::Relaxed),
+                failures: b.failures.load(Ordering::Relaxed),
+                ejections: b.ejections.load(Ordering::Relaxed),
+                ejected: b.ejected_until.load(Ordering::Relaxed) > now,
+            })
+            .collect()
+    }
+
+    /// Re-reads `path` whenever it changes, dropping endpoints no longer
+    /// listed and adding new ones with the channels `connect` makes. Stops
+    /// once every clone of the balancer is gone.
+    pub fn watch<F>(&self, path: PathBuf, connect: F)
+    where
+        F: Fn(&str) -> Result<Vec<Channel>, String> + Send + 'static,
+    {
+        let shared = Arc::downgrade(&self.shared);
+        let mut modified = modified_at(&path);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(balancer) = Weak::upgrade(&shared).map(|shared| Balancer { shared }) else {
+                    return;
+                };
+                let now = modified_at(&path);
+                if now == modified {
+                    continue;
+                }
+                modified = now;
+                match read_endpoints(&path) {
+                    Ok(urls) => balancer.update(&urls, &connect),
+                    Err(e) => warn!("Keeping the current endpoints: {}", e),
+                }
+            }
+        });
+    }
+
+    fn update(&self, urls: &[String], connect: &impl Fn(&str) -> Result<Vec<Channel>, String>) {
+        let current = self.urls();
+        self.retain(urls);
+        for url in urls.iter().filter(|url| !current.contains(url)) {
+            match connect(url) {
+                Ok(channels) => self.insert(url.clone(), channels),
+                Err(e) => warn!("Skipping endpoint {}: {}", url, e),
+            }
+        }
+        info!("Endpoints now {}", urls.join(", "));
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reads one URL per line; blank lines and `#` comments are skipped.
+pub fn read_endpoints(path: &Path) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let urls: Vec<String> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if urls.is_empty() {
+        return Err(format!("{} lists no endpoints", path.display()));
+    }
+    Ok(urls)
+}
+
+/// Splits a comma-separated `--server` list.
+pub fn split_urls(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|url| !url.is_empty()).map(str::to_string).collect()
+}
+
+/// One connection's view of a `Balancer`: every call goes to the chosen
+/// endpoint's channel for this connection.
+#[derive(Debug, Clone)]
+pub struct BalancedChannel {
+    shared: Arc<Shared>,
+    connection: usize,
+}
+
+impl Service<http::Request<BoxBody>> for BalancedChannel {
+    type Response = http::Response<BoxBody>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Readiness is checked on the chosen channel once the call is routed
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let Some(backend) = self.shared.pick() else {
+            return Box::pin(async { Err(Status::unavailable("no endpoints to send to").into()) });
+        };
+        let channel = backend.channels[self.connection % backend.channels.len()].clone();
+        backend.calls.fetch_add(1, Ordering::Relaxed);
+        backend.outstanding.fetch_add(1, Ordering::Relaxed);
+        let outstanding = Outstanding(backend);
+        let shared = self.shared.clone();
+        Box::pin(async move {
+            match channel.oneshot(request).await {
+                Ok(response) => {
+                    // Trailers-only UNAVAILABLE answers, e.g. from a draining server
+                    let unavailable = response.headers().get("grpc-status").is_some_and(|s| s == "14");
+                    shared.record(&outstanding.0, !unavailable);
+                    Ok(response.map(|body| tonic::body::boxed(TrackedBody { body, _outstanding: outstanding })))
+                }
+                Err(e) => {
+                    shared.record(&outstanding.0, false);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+}
+
+/// Counts a call as outstanding on its endpoint until dropped.
+struct Outstanding(Arc<Backend>);
+
+impl Drop for Outstanding {
+    fn drop(&mut self) {
+        self.0.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps a call outstanding until its response body, including any
+/// stream, is finished with.
+struct TrackedBody {
+    body: BoxBody,
+    _outstanding: Outstanding,
+}
+
+impl http_body::Body for TrackedBody {
+    type Data = <BoxBody as http_body::Body>::Data;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Load-balancing flags shared by the client and the benchmark. `--server`
+/// takes a comma-separated list to balance over.
+#[derive(Args, Debug, Clone)]
+// Keeps this doc comment from becoming the `about` of the binaries
+#[command(about = None, long_about = None)]
+pub struct BalanceArgs {
+    /// Read the servers from this file, one URL per line, instead of
+    /// --server; changes are picked up while running
+    #[arg(long, conflicts_with = "server")]
+    pub endpoints_file: Option<PathBuf>,
+
+    /// How calls are spread over several servers
+    #[arg(long, value_enum, default_value_t = BalancePolicy::RoundRobin)]
+    pub balance: BalancePolicy,
+
+    /// Eject a server after this many failed calls in a row; 0 never ejects
+    #[arg(long, default_value_t = 5)]
+    pub eject_after: u32,
+
+    /// How long an ejected server gets no calls
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    pub eject_for: Duration,
+}
+
+impl BalanceArgs {
+    pub fn config(&self) -> BalanceConfig {
+        BalanceConfig {
+            policy: self.balance,
+            eject_after: self.eject_after,
+            eject_for: self.eject_for,
+        }
+    }
+
+    /// The endpoints file's URLs, or else those of `server`.
+    pub fn urls(&self, server: &str) -> Result<Vec<String>, String> {
+        match &self.endpoints_file {
+            Some(path) => read_endpoints(path),
+            None => match split_urls(server) {
+                urls if urls.is_empty() => Err("--server lists no endpoints".to_string()),
+                urls => Ok(urls),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ejections last far longer than any test; tests end them by hand.
+    fn balancer(policy: BalancePolicy, urls: &[&str]) -> Balancer {
+        let config = BalanceConfig {
+            policy,
+            eject_after: 3,
+            eject_for: Duration::from_secs(3600),
+        };
+        balancer_with(config, urls)
+    }
+
+    /// A balancer over `urls` whose endpoints have no channels, for picking only.
+    fn balancer_with(config: BalanceConfig, urls: &[&str]) -> Balancer {
+        let balancer = Balancer::new(config);
+        let backends = urls
+            .iter()
+            .map(|url| {
+                Arc::new(Backend {
+                    url: url.to_string(),
+                    channels: Vec::new(),
+                    outstanding: AtomicUsize::new(0),
+                    consecutive_failures: AtomicU32::new(0),
+                    ejected_until: AtomicU64::new(0),
+                    calls: AtomicU64::new(0),
+                    failures: AtomicU64::new(0),
+                    ejections: AtomicU64::new(0),
+                })
+            })
+            .collect();
+        *balancer.shared.backends.write().unwrap() = Arc::new(backends);
+        balancer
+    }
+
+    fn backend(balancer: &Balancer, url: &str) -> Arc<Backend> {
+        let backends = balancer.shared.backends.read().unwrap();
+        backends.iter().find(|b| b.url == url).unwrap().clone()
+    }
+
+    fn picks(balancer: &Balancer, n: usize) -> Vec<String> {
+        (0..n).map(|_| balancer.shared.pick().unwrap().url.clone()).collect()
+    }
+
+    fn fail(balancer: &Balancer, url: &str, times: usize) {
+        let backend = backend(balancer, url);
+        for _ in 0..times {
+            balancer.shared.record(&backend, false);
+        }
+    }
+
+    #[test]
+    fn nothing_to_pick_without_endpoints() {
+        assert!(balancer(BalancePolicy::RoundRobin, &[]).shared.pick().is_none());
+    }
+
+    #[test]
+    fn round_robin_takes_each_endpoint_in_turn() {
+        let balancer = balancer(BalancePolicy::RoundRobin, &["a", "b", "c"]);
+        assert_eq!(picks(&balancer, 6), ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn least_outstanding_picks_the_least_busy() {
+        let balancer = balancer(BalancePolicy::LeastOutstanding, &["a", "b", "c"]);
+        backend(&balancer, "a").outstanding.store(2, Ordering::Relaxed);
+        backend(&balancer, "c").outstanding.store(1, Ordering::Relaxed);
+        assert!(picks(&balancer, 10).iter().all(|url| url == "b"));
+    }
+
+    #[test]
+    fn least_outstanding_spreads_ties() {
+        let balancer = balancer(BalancePolicy::LeastOutstanding, &["a", "b", "c"]);
+        let mut picked = picks(&balancer, 3);
+        picked.sort();
+        assert_eq!(picked, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn power_of_two_choices_never_picks_the_busiest() {
+        let balancer = balancer(BalancePolicy::PowerOfTwoChoices, &["a", "b", "c"]);
+        backend(&balancer, "b").outstanding.store(1, Ordering::Relaxed);
+        backend(&balancer, "c").outstanding.store(10, Ordering::Relaxed);
+        let picked = picks(&balancer, 1000);
+        assert!(!picked.iter().any(|url| url == "c"));
+        assert!(picked.iter().any(|url| url == "a"));
+    }
+
+    #[test]
+    fn power_of_two_choices_with_one_endpoint() {
+        let balancer = balancer(BalancePolicy::PowerOfTwoChoices, &["a"]);
+        assert_eq!(picks(&balancer, 3), ["a", "a", "a"]);
+    }
+
+    #[test]
+    fn ejects_after_consecutive_failures() {
+        let balancer = balancer(BalancePolicy::RoundRobin, &["a", "b"]);
+        fail(&balancer, "a", 2);
+        // A success starts the count again
+        balancer.shared.record(&backend(&balancer, "a"), true);
+        fail(&balancer, "a", 2);
+        assert!(picks(&balancer, 4).iter().any(|url| url == "a"));
+
+        fail(&balancer, "a", 1);
+        assert_eq!(picks(&balancer, 4), ["b", "b", "b", "b"]);
+        let stats = balancer.stats();
+        assert!(stats[0].ejected);
+        assert_eq!((stats[0].failures, stats[0].ejections), (5, 1));
+        assert!(!stats[1].ejected);
+    }
+
+    #[test]
+    fn readmits_after_the_ejection_ends() {
+        let balancer = balancer(BalancePolicy::RoundRobin, &["a", "b"]);
+        let now = balancer.shared.now_micros();
+        fail(&balancer, "a", 3);
+        let a = backend(&balancer, "a");
+        let until = a.ejected_until.load(Ordering::Relaxed);
+        assert!(until >= now + 3_600_000_000);
+        assert!(picks(&balancer, 4).iter().all(|url| url == "b"));
+
+        // Ends the ejection instead of waiting an hour for it
+        a.ejected_until.store(balancer.shared.now_micros(), Ordering::Relaxed);
+        assert!(!balancer.stats()[0].ejected);
+        assert_eq!(picks(&balancer, 4), ["a", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn uses_every_endpoint_when_all_are_ejected() {
+        let balancer = balancer(BalancePolicy::LeastOutstanding, &["a", "b"]);
+        fail(&balancer, "a", 3);
+        fail(&balancer, "b", 3);
+        let mut picked = picks(&balancer, 2);
+        picked.sort();
+        assert_eq!(picked, ["a", "b"]);
+    }
+
+    #[test]
+    fn never_ejects_when_disabled() {
+        let config = BalanceConfig {
+            eject_after: 0,
+            ..BalanceConfig::default()
+        };
+        let never = balancer_with(config, &["a", "b"]);
+        fail(&never, "a", 100);
+        assert_eq!(picks(&never, 2), ["a", "b"]);
+        assert_eq!(never.stats()[0].ejections, 0);
+    }
+
+    #[test]
+    fn parses_endpoint_lists() {
+        assert_eq!(split_urls(" http://a:1, ,http://b:2 "), ["http://a:1", "http://b:2"]);
+        let path = std::env::temp_dir().join(format!("grpc-demo-endpoints-{}", std::process::id()));
+        std::fs::write(&path, "# servers\nhttp://a:1\n\n  http://b:2  # slow one\n").unwrap();
+        assert_eq!(read_endpoints(&path).unwrap(), ["http://a:1", "http://b:2"]);
+        std::fs::write(&path, "# nothing yet\n").unwrap();
+        assert!(read_endpoints(&path).is_err());
+    }
+}
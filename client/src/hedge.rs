@@ -0,0 +1,388 @@
+//! Hedged calls: when a response is slow to come back, send the same call
+//! again and take whichever succeeds first, cancelling the other. This cuts
+//! tail latency at the cost of a little extra load, capped by
+//! `max_fraction`. Only hedge idempotent calls such as `SayHello`.
+//!
+//! ```ignore
+//! let hedge = HedgePolicy::new("p95".parse()?, 0.05);
+//! let (result, stats) = hedge.call(|| {
+//!     let mut client = client.clone();
+//!     async move { client.say_hello(HelloRequest { name: "World".into() }).await }
+//! }).await;
+//! ```
+
+use clap::Args;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+/// Latencies of the most recent successful calls a percentile is taken over.
+const WINDOW: usize = 1000;
+/// Calls to observe before a percentile delay starts hedging; the first
+/// percentile is taken as soon as there are this many.
+const MIN_SAMPLES: usize = 100;
+/// Recompute the percentile after this many new samples.
+const REFRESH_EVERY: u64 = 64;
+
+/// How long to wait for a response before sending the hedge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HedgeDelay {
+    Fixed(Duration),
+    /// That quantile, between 0 and 1, of recent successful calls.
+    Percentile(f64),
+}
+
+impl FromStr for HedgeDelay {
+    type Err = String;
+
+    /// Parses `20ms` or `p95`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percentile) = s.strip_prefix('p') {
+            return match percentile.parse::<f64>() {
+                Ok(p) if p > 0.0 && p < 100.0 => Ok(HedgeDelay::Percentile(p / 100.0)),
+                _ => Err(format!("'{}' is not a percentile like p95 or p99.9", s)),
+            };
+        }
+        humantime::parse_duration(s)
+            .map(HedgeDelay::Fixed)
+            .map_err(|_| format!("'{}' is not a delay like 20ms or a percentile like p95", s))
+    }
+}
+
+impl fmt::Display for HedgeDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HedgeDelay::Fixed(delay) => write!(f, "{:?}", delay),
+            HedgeDelay::Percentile(quantile) => write!(f, "p{}", quantile * 100.0),
+        }
+    }
+}
+
+/// Clones share the observed latencies, the hedge cap and the counts.
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    pub after: HedgeDelay,
+    /// Share of calls that may be hedged, between 0 and 1.
+    pub max_fraction: f64,
+    state: Arc<HedgeState>,
+}
+
+#[derive(Debug, Default)]
+struct HedgeState {
+    calls: AtomicU64,
+    hedged: AtomicU64,
+    hedge_wins: AtomicU64,
+    latencies: Mutex<VecDeque<Duration>>,
+    recorded: AtomicU64,
+    /// Cached percentile in microseconds; 0 until enough calls were seen.
+    percentile_micros: AtomicU64,
+}
+
+/// What hedging did for one call.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HedgeStats {
+    pub hedged: bool,
+    /// The hedge answered first.
+    pub hedge_won: bool,
+}
+
+impl HedgePolicy {
+    pub fn new(after: HedgeDelay, max_fraction: f64) -> Self {
+        Self {
+            after,
+            max_fraction,
+            state: Arc::default(),
+        }
+    }
+
+    /// Calls made through this policy and its clones.
+    pub fn calls(&self) -> u64 {
+        self.state.calls.load(Ordering::Relaxed)
+    }
+
+    pub fn hedged(&self) -> u64 {
+        self.state.hedged.load(Ordering::Relaxed)
+    }
+
+    pub fn hedge_wins(&self) -> u64 {
+        self.state.hedge_wins.load(Ordering::Relaxed)
+    }
+
+    /// Runs `attempt`, and runs it again if no response arrived after the
+    /// hedge delay and the cap allows. The first success wins and the other
+    /// call is dropped, which cancels it; if both fail, the first error is
+    /// returned.
+    pub async fn call<T, F, Fut>(&self, mut attempt: F) -> (Result<T, Status>, HedgeStats)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let started = Instant::now();
+        self.state.calls.fetch_add(1, Ordering::Relaxed);
+        let mut stats = HedgeStats::default();
+
+        let primary = attempt();
+        tokio::pin!(primary);
+        let result = match self.delay() {
+            None => primary.await,
+            Some(delay) => tokio::select! {
+                result = &mut primary => result,
+                _ = tokio::time::sleep(delay) => {
+                    if self.take_hedge() {
+                        stats.hedged = true;
+                        let hedge = attempt();
+                        tokio::pin!(hedge);
+                        let (result, hedge_first) = tokio::select! {
+                            result = &mut primary => (result, false),
+                            result = &mut hedge => (result, true),
+                        };
+                        match result {
+                            Ok(value) => {
+                                stats.hedge_won = hedge_first;
+                                Ok(value)
+                            }
+                            // The other call may still succeed
+                            Err(first) => {
+                                let other = if hedge_first { primary.await } else { hedge.await };
+                                stats.hedge_won = !hedge_first && other.is_ok();
+                                other.map_err(|_| first)
+                            }
+                        }
+                    } else {
+                        primary.await
+                    }
+                }
+            },
+        };
+
+        if stats.hedge_won {
+            self.state.hedge_wins.fetch_add(1, Ordering::Relaxed);
+        }
+        if result.is_ok() {
+            self.observe(started.elapsed());
+        }
+        (result, stats)
+    }
+
+    /// The current hedge delay; `None` while a percentile has too few samples.
+    pub fn delay(&self) -> Option<Duration> {
+        match self.after {
+            HedgeDelay::Fixed(delay) => Some(delay),
+            HedgeDelay::Percentile(_) => match self.state.percentile_micros.load(Ordering::Relaxed) {
+                0 => None,
+                micros => Some(Duration::from_micros(micros)),
+            },
+        }
+    }
+
+    /// Counts a hedge unless that would hedge more than `max_fraction` of calls.
+    fn take_hedge(&self) -> bool {
+        let allowed = self.calls() as f64 * self.max_fraction;
+        self.state
+            .hedged
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hedged| {
+                ((hedged + 1) as f64 <= allowed).then_some(hedged + 1)
+            })
+            .is_ok()
+    }
+
+    fn observe(&self, latency: Duration) {
+        let HedgeDelay::Percentile(quantile) = self.after else {
+            return;
+        };
+        let mut latencies = self.state.latencies.lock().unwrap();
+        if latencies.len() == WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+        let recorded = self.state.recorded.fetch_add(1, Ordering::Relaxed) + 1;
+        let refresh = recorded == MIN_SAMPLES as u64 || recorded.is_multiple_of(REFRESH_EVERY);
+        if latencies.len() < MIN_SAMPLES || !refresh {
+            return;
+        }
+        let mut sorted: Vec<_> = latencies.iter().copied().collect();
+        drop(latencies);
+        let index = ((sorted.len() as f64 * quantile) as usize).min(sorted.len() - 1);
+        let (_, percentile, _) = sorted.select_nth_unstable(index);
+        let micros = u64::try_from(percentile.as_micros()).unwrap_or(u64::MAX).max(1);
+        self.state.percentile_micros.store(micros, Ordering::Relaxed);
+    }
+}
+
+/// Hedging flags shared by the client and the benchmark.
+#[derive(Args, Debug, Clone)]
+// Keeps this doc comment from becoming the `about` of the binaries
+#[command(about = None, long_about = None)]
+pub struct HedgeArgs {
+    /// Send a duplicate SayHello when no response arrived after this long,
+    /// e.g. 20ms, or after a percentile of recent calls, e.g. p95
+    #[arg(long)]
+    pub hedge_after: Option<HedgeDelay>,
+
+    /// Most calls that may be hedged, as a fraction of all calls
+    #[arg(long, value_parser = parse_fraction, default_value_t = 0.1, requires = "hedge_after")]
+    pub hedge_max_fraction: f64,
+}
+
+impl HedgeArgs {
+    /// A policy with fresh counts, if hedging is on.
+    pub fn policy(&self) -> Option<HedgePolicy> {
+        self.hedge_after
+            .map(|after| HedgePolicy::new(after, self.hedge_max_fraction))
+    }
+}
+
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("'{}' is not a fraction between 0 and 1", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Attempt `i` answers after `script[i].0` with `script[i].1`: `Ok(i)` or a status with that code.
+    async fn run(policy: &HedgePolicy, script: &[(u64, Result<(), tonic::Code>)]) -> (Result<usize, Status>, HedgeStats) {
+        let next = AtomicUsize::new(0);
+        policy
+            .call(|| {
+                let attempt = next.fetch_add(1, Ordering::Relaxed);
+                let (millis, outcome) = script[attempt];
+                async move {
+                    tokio::time::sleep(Duration::from_millis(millis)).await;
+                    outcome.map(|()| attempt).map_err(|code| Status::new(code, format!("attempt {}", attempt)))
+                }
+            })
+            .await
+    }
+
+    fn fixed(millis: u64) -> HedgePolicy {
+        HedgePolicy::new(HedgeDelay::Fixed(Duration::from_millis(millis)), 1.0)
+    }
+
+    #[test]
+    fn parses_delays_and_percentiles() {
+        assert_eq!("20ms".parse(), Ok(HedgeDelay::Fixed(Duration::from_millis(20))));
+        assert_eq!("p95".parse(), Ok(HedgeDelay::Percentile(0.95)));
+        assert_eq!("p99.9".parse::<HedgeDelay>().unwrap().to_string(), "p99.9");
+        for bad in ["p0", "p100", "pfast", "20", "soon"] {
+            assert!(bad.parse::<HedgeDelay>().is_err(), "{} was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn percentile_waits_for_enough_samples() {
+        let policy = HedgePolicy::new(HedgeDelay::Percentile(0.9), 0.1);
+        for millis in 1..MIN_SAMPLES as u64 {
+            policy.observe(Duration::from_millis(millis));
+        }
+        assert_eq!(policy.delay(), None);
+        policy.observe(Duration::from_millis(MIN_SAMPLES as u64));
+        // 90% of 1..=100ms
+        assert_eq!(policy.delay(), Some(Duration::from_millis(91)));
+    }
+
+    #[test]
+    fn percentile_follows_recent_latencies() {
+        let policy = HedgePolicy::new(HedgeDelay::Percentile(0.5), 0.1);
+        for _ in 0..WINDOW {
+            policy.observe(Duration::from_millis(10));
+        }
+        assert_eq!(policy.delay(), Some(Duration::from_millis(10)));
+        for _ in 0..WINDOW {
+            policy.observe(Duration::from_millis(30));
+        }
+        assert_eq!(policy.delay(), Some(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn hedges_at_most_the_allowed_fraction_of_calls() {
+        let policy = HedgePolicy::new(HedgeDelay::Fixed(Duration::ZERO), 0.1);
+        policy.state.calls.store(10, Ordering::Relaxed);
+        assert!(policy.take_hedge());
+        assert!(!policy.take_hedge());
+        policy.state.calls.store(20, Ordering::Relaxed);
+        assert!(policy.take_hedge());
+        assert!(!policy.take_hedge());
+        assert_eq!(policy.hedged(), 2);
+
+        let never = HedgePolicy::new(HedgeDelay::Fixed(Duration::ZERO), 0.0);
+        never.state.calls.store(1000, Ordering::Relaxed);
+        assert!(!never.take_hedge());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fast_calls_are_not_hedged() {
+        let policy = fixed(10);
+        let (result, stats) = run(&policy, &[(5, Ok(()))]).await;
+        assert_eq!(result.unwrap(), 0);
+        assert!(!stats.hedged);
+        assert_eq!((policy.calls(), policy.hedged()), (1, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_faster_hedge_wins() {
+        let policy = fixed(10);
+        let (result, stats) = run(&policy, &[(100, Ok(())), (5, Ok(()))]).await;
+        assert_eq!(result.unwrap(), 1);
+        assert!(stats.hedged && stats.hedge_won);
+        assert_eq!(policy.hedge_wins(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_primary_that_answers_first_wins() {
+        let policy = fixed(10);
+        let (result, stats) = run(&policy, &[(20, Ok(())), (50, Ok(()))]).await;
+        assert_eq!(result.unwrap(), 0);
+        assert!(stats.hedged && !stats.hedge_won);
+        assert_eq!(policy.hedge_wins(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_hedge_falls_back_to_the_primary() {
+        let policy = fixed(10);
+        let (result, stats) = run(&policy, &[(50, Ok(())), (1, Err(tonic::Code::Unavailable))]).await;
+        assert_eq!(result.unwrap(), 0);
+        assert!(stats.hedged && !stats.hedge_won);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_primary_falls_back_to_the_hedge() {
+        let policy = fixed(10);
+        let (result, stats) = run(&policy, &[(15, Err(tonic::Code::Unavailable)), (50, Ok(()))]).await;
+        assert_eq!(result.unwrap(), 1);
+        assert!(stats.hedged && stats.hedge_won);
+        assert_eq!(policy.hedge_wins(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn both_failing_returns_the_first_error() {
+        let policy = fixed(10);
+        let script = [(30, Err(tonic::Code::Internal)), (1, Err(tonic::Code::Unavailable))];
+        let (result, stats) = run(&policy, &script).await;
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unavailable);
+        assert!(stats.hedged && !stats.hedge_won);
+        assert_eq!(policy.hedge_wins(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_calls_past_the_cap_are_not_hedged() {
+        let policy = HedgePolicy::new(HedgeDelay::Fixed(Duration::from_millis(10)), 0.5);
+        let mut hedged = 0;
+        for _ in 0..10 {
+            let (result, stats) = run(&policy, &[(20, Ok(())), (20, Ok(()))]).await;
+            assert!(result.is_ok());
+            hedged += stats.hedged as u64;
+        }
+        assert_eq!(hedged, 5);
+        assert_eq!(policy.hedged(), 5);
+    }
+}
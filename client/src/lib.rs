@@ -0,0 +1,11 @@
+//! Client-side helpers shared by `grpc-demo-client` and the benchmark.
+
+pub mod balance;
+pub mod hedge;
+pub mod retry;
+pub mod tls;
+
+pub use balance::{BalanceArgs, BalanceConfig, BalancePolicy, BalancedChannel, Balancer};
+pub use hedge::{HedgeArgs, HedgePolicy, HedgeStats};
+pub use retry::{RetryArgs, RetryPolicy, RetryStats};
+pub use tls::TlsArgs;
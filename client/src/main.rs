@@ -1,53 +1,204 @@
-use grpc_demo_proto::{greeter_service_client::GreeterServiceClient, HelloRequest};
+use clap::{Parser, Subcommand};
+use grpc_demo_client::{
+    BalanceArgs, BalancedChannel, Balancer, HedgeArgs, HedgePolicy, RetryArgs, RetryPolicy, RetryStats, TlsArgs,
+};
+use grpc_demo_proto::{greeter_service_client::GreeterServiceClient, HelloRequest, HelloResponse};
 use tokio_stream::StreamExt;
 use tonic::transport::{Channel, Endpoint};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
 use std::time::Duration;
 
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server address, or a comma-separated list to balance calls over
+    #[arg(short, long, default_value = "http://[::1]:50051")]
+    server: String,
+
+    #[command(flatten)]
+    balance: BalanceArgs,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    retry: RetryArgs,
+
+    #[command(flatten)]
+    hedge: HedgeArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the unary and streaming demo calls (default)
+    Demo,
+    /// Query grpc.health.v1.Health; exits non-zero unless SERVING
+    Health {
+        /// Service to check; empty for the whole server
+        #[arg(long, default_value = "")]
+        service: String,
+    },
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Create connection with pooling configuration
-    let endpoint = Endpoint::from_static("http://[::1]:50051")
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args = Args::parse();
+    let urls = args.balance.urls(&args.server)?;
+
+    // Create one pooled connection per server. With several servers, one
+    // that is down is skipped for now and retried lazily.
+    let mut channels = Vec::new();
+    let mut failed = Vec::new();
+    for url in &urls {
+        let endpoint = configure(args.tls.endpoint(url.clone())?);
+        match endpoint.connect().await {
+            Ok(channel) => channels.push((url.clone(), channel)),
+            Err(e) if urls.len() == 1 => return Err(e.into()),
+            Err(e) => {
+                eprintln!("warning: cannot connect to {}: {}", url, e);
+                channels.push((url.clone(), endpoint.connect_lazy()));
+                failed.push(e);
+            }
+        }
+    }
+    if failed.len() == urls.len() {
+        return Err(failed.remove(0).into());
+    }
+
+    match args.command.unwrap_or(Command::Demo) {
+        Command::Demo => {
+            let balancer = Balancer::new(args.balance.config());
+            for (url, channel) in channels {
+                balancer.insert(url, vec![channel]);
+            }
+            if let Some(path) = args.balance.endpoints_file.clone() {
+                let tls = args.tls.clone();
+                balancer.watch(path, move |url| {
+                    let endpoint = tls.endpoint(url).map_err(|e| e.to_string())?;
+                    Ok(vec![configure(endpoint).connect_lazy()])
+                });
+            }
+            demo(&balancer, args.retry.policy(), args.hedge.policy()).await
+        }
+        Command::Health { service } => health(channels, service).await,
+    }
+}
+
+/// Connection pooling configuration for every server.
+fn configure(endpoint: Endpoint) -> Endpoint {
+    endpoint
         .timeout(Duration::from_secs(30))
         .tcp_keepalive(Some(Duration::from_secs(600)))
         .tcp_nodelay(true)
         .http2_keep_alive_interval(Duration::from_secs(30))
-        .keep_alive_while_idle(true);
+        .keep_alive_while_idle(true)
+}
 
-    let channel = endpoint.connect().await?;
-    let mut client = GreeterServiceClient::new(channel);
+/// Checks every server; exits non-zero unless all of them are serving.
+async fn health(channels: Vec<(String, Channel)>, service: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let several = channels.len() > 1;
+    let mut serving = true;
+    for (url, channel) in channels {
+        let mut client = HealthClient::new(channel);
+        let request = HealthCheckRequest { service: service.clone() };
+        let target = if service.is_empty() { "server" } else { service.as_str() };
+        let status = match client.check(request).await {
+            Ok(response) => response.into_inner().status(),
+            Err(e) if several => {
+                println!("{} {}: {}", url, target, e.message());
+                serving = false;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if several {
+            println!("{} {}: {:?}", url, target, status);
+        } else {
+            println!("{}: {:?}", target, status);
+        }
+        serving &= status == ServingStatus::Serving;
+    }
+    if !serving {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints how many retries a call needed, if any.
+fn report_retries(stats: RetryStats) {
+    if stats.retries() > 0 {
+        println!("  (retries: {})", stats.retries());
+    }
+}
+
+/// SayHello, hedged when `hedge` is set.
+async fn say_hello(
+    client: &GreeterServiceClient<BalancedChannel>,
+    hedge: Option<&HedgePolicy>,
+    name: String,
+) -> Result<tonic::Response<HelloResponse>, tonic::Status> {
+    let attempt = || {
+        let mut client = client.clone();
+        let request = tonic::Request::new(HelloRequest { name: name.clone() });
+        async move { client.say_hello(request).await }
+    };
+    match hedge {
+        Some(hedge) => hedge.call(attempt).await.0,
+        None => attempt().await,
+    }
+}
+
+async fn demo(
+    balancer: &Balancer,
+    retry: RetryPolicy,
+    hedge: Option<HedgePolicy>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = GreeterServiceClient::new(balancer.connection(0));
+    let hedge = hedge.as_ref();
 
     println!("=== Connected with Connection Pooling ===");
+    if retry.enabled() {
+        println!("Retrying {:?} up to {} attempts per call", retry.retryable, retry.max_attempts);
+    }
+    if let Some(hedge) = hedge {
+        println!("Hedging SayHello after {}, at most {} of calls", hedge.after, hedge.max_fraction);
+    }
 
     // Single request
     println!("=== Single Request ===");
-    let request = tonic::Request::new(HelloRequest {
-        name: "World".into(),
-    });
-
-    let response = client.say_hello(request).await?;
-    println!("RESPONSE={:?}", response.into_inner());
+    let (response, stats) = retry.call(|| say_hello(&client, hedge, "World".into())).await;
+    println!("RESPONSE={:?}", response?.into_inner());
+    report_retries(stats);
 
     // Multiple requests to test connection reuse
     println!("\n=== Multiple Requests (Connection Reuse Test) ===");
     for i in 1..=5 {
-        let request = tonic::Request::new(HelloRequest {
-            name: format!("Request {}", i),
-        });
-
         let start = std::time::Instant::now();
-        let response = client.say_hello(request).await?;
+        let (response, stats) = retry.call(|| say_hello(&client, hedge, format!("Request {}", i))).await;
         let duration = start.elapsed();
         
-        println!("Request {}: {:?} (took: {:?})", i, response.into_inner(), duration);
+        println!("Request {}: {:?} (took: {:?})", i, response?.into_inner(), duration);
+        report_retries(stats);
     }
 
     // Streaming request
     println!("\n=== Streaming Request ===");
-    let request = tonic::Request::new(HelloRequest {
-        name: "Streaming World".into(),
-    });
-
-    let mut stream = client.say_hello_stream(request).await?.into_inner();
+    // Only opening the stream is retried; messages already received are not replayed
+    let (stream, stats) = retry
+        .call(|| {
+            let mut client = client.clone();
+            let request = tonic::Request::new(HelloRequest {
+                name: "Streaming World".into(),
+            });
+            async move { client.say_hello_stream(request).await }
+        })
+        .await;
+    report_retries(stats);
+    let mut stream = stream?.into_inner();
 
     while let Some(response) = stream.next().await {
         match response {
@@ -61,6 +212,64 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Client-streaming request
+    println!("\n=== Client Streaming Request ===");
+    let (response, stats) = retry
+        .call(|| {
+            let mut client = client.clone();
+            let names = ["Alice", "Bob", "Carol"].map(|name| HelloRequest { name: name.into() });
+            async move { client.say_hello_client_stream(tokio_stream::iter(names)).await }
+        })
+        .await;
+    println!("CLIENT STREAM RESPONSE={:?}", response?.into_inner());
+    report_retries(stats);
+
+    // Bidirectional streaming request
+    println!("\n=== Bidirectional Chat ===");
+    let (replies, stats) = retry
+        .call(|| {
+            let mut client = client.clone();
+            let outbound = tokio_stream::iter(1..=3).map(|i| HelloRequest {
+                name: format!("Chat {}", i),
+            });
+            async move { client.say_hello_chat(outbound).await }
+        })
+        .await;
+    report_retries(stats);
+    let mut replies = replies?.into_inner();
+
+    while let Some(response) = replies.next().await {
+        match response {
+            Ok(hello_response) => {
+                println!("CHAT RESPONSE={:?}", hello_response);
+            }
+            Err(e) => {
+                println!("CHAT ERROR={:?}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(hedge) = hedge {
+        println!(
+            "\nHedged {} of {} SayHello calls; the hedge answered first {} times",
+            hedge.hedged(),
+            hedge.calls(),
+            hedge.hedge_wins()
+        );
+    }
+
+    let endpoints = balancer.stats();
+    if endpoints.len() > 1 {
+        println!("\n=== Endpoints ===");
+        for endpoint in endpoints {
+            println!(
+                "{}: {} calls, {} failed, ejected {} times",
+                endpoint.url, endpoint.calls, endpoint.failures, endpoint.ejections
+            );
+        }
+    }
+
     println!("\n=== Connection Pooling Test Complete ===");
     Ok(())
 }
@@ -0,0 +1,376 @@
+//! Retries for `GreeterServiceClient` calls, following the gRPC retry design:
+//! per-status-code retryability, exponential backoff with full jitter, a
+//! maximum number of attempts, a token-bucket budget that stops retry storms,
+//! and server pushback through `grpc-retry-pushback-ms`.
+//!
+//! Calls are retried whole, so a closure re-issues each attempt:
+//!
+//! ```ignore
+//! let (result, stats) = policy.call(|| {
+//!     let mut client = client.clone();
+//!     async move { client.say_hello(HelloRequest { name: "World".into() }).await }
+//! }).await;
+//! ```
+
+use clap::Args;
+use rand::Rng;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tonic::{Code, Status};
+
+/// Trailing metadata a server sets to say when to retry, or, when negative,
+/// not to retry at all.
+pub const PUSHBACK_METADATA: &str = "grpc-retry-pushback-ms";
+
+/// Share of a token a successful call returns to the budget.
+const TOKEN_RATIO: f64 = 0.1;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts per call, including the first; 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Status codes worth retrying.
+    pub retryable: Vec<Code>,
+    /// Shared by every call made with this policy or its clones.
+    pub budget: Option<Arc<RetryBudget>>,
+}
+
+impl Default for RetryPolicy {
+    /// No retries.
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            retryable: vec![Code::Unavailable],
+            budget: None,
+        }
+    }
+}
+
+/// What retrying did for one call.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryStats {
+    pub attempts: u32,
+    /// The budget refused a retry the policy would have made.
+    pub throttled: bool,
+    /// Retries whose delay the server chose.
+    pub pushbacks: u32,
+}
+
+impl RetryStats {
+    pub fn retries(&self) -> u32 {
+        self.attempts.saturating_sub(1)
+    }
+}
+
+impl RetryPolicy {
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    /// Runs `attempt` until it succeeds, fails with a code that is not
+    /// retryable, or the attempts, the budget or the server say stop.
+    pub async fn call<T, F, Fut>(&self, mut attempt: F) -> (Result<T, Status>, RetryStats)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut stats = RetryStats::default();
+        loop {
+            stats.attempts += 1;
+            let status = match attempt().await {
+                Ok(value) => {
+                    if let Some(budget) = &self.budget {
+                        budget.succeeded();
+                    }
+                    return (Ok(value), stats);
+                }
+                Err(status) => status,
+            };
+
+            // Pushback only changes when a retryable failure is retried
+            if !self.retryable.contains(&status.code()) {
+                return (Err(status), stats);
+            }
+            if let Some(budget) = &self.budget {
+                budget.failed();
+            }
+            if stats.attempts >= self.max_attempts {
+                return (Err(status), stats);
+            }
+            let delay = match pushback(&status) {
+                Some(Some(delay)) => {
+                    stats.pushbacks += 1;
+                    delay
+                }
+                Some(None) => return (Err(status), stats),
+                None => self.backoff(stats.attempts),
+            };
+            if self.budget.as_ref().is_some_and(|budget| !budget.allows_retry()) {
+                stats.throttled = true;
+                return (Err(status), stats);
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// A random delay of up to `initial * multiplier^(attempt - 1)`, capped
+    /// at `max_backoff`, before retry number `attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        if self.initial_backoff.is_zero() {
+            return Duration::ZERO;
+        }
+        // In seconds, so late attempts reach infinity rather than overflow a Duration
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let ceiling = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        Duration::try_from_secs_f64(ceiling * rand::thread_rng().gen::<f64>()).unwrap_or(self.max_backoff)
+    }
+}
+
+/// `None` without pushback, `Some(None)` when the server said not to retry.
+fn pushback(status: &Status) -> Option<Option<Duration>> {
+    let value = status.metadata().get(PUSHBACK_METADATA)?;
+    let millis = value.to_str().ok().and_then(|v| v.parse::<i64>().ok());
+    Some(millis.and_then(|ms| u64::try_from(ms).ok()).map(Duration::from_millis))
+}
+
+/// gRPC retry throttling: every failed attempt takes a token, every success
+/// returns a tenth of one, and retries stop while half or fewer are left.
+#[derive(Debug)]
+pub struct RetryBudget {
+    max_tokens: f64,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub fn new(max_tokens: u32) -> Self {
+        Self {
+            max_tokens: max_tokens as f64,
+            tokens: Mutex::new(max_tokens as f64),
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + TOKEN_RATIO).min(self.max_tokens);
+    }
+
+    fn failed(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens - 1.0).max(0.0);
+    }
+
+    fn allows_retry(&self) -> bool {
+        *self.tokens.lock().unwrap() > self.max_tokens / 2.0
+    }
+}
+
+/// Retry flags shared by the client and the benchmark.
+#[derive(Args, Debug, Clone)]
+// Keeps this doc comment from becoming the `about` of the binaries
+#[command(about = None, long_about = None)]
+pub struct RetryArgs {
+    /// Attempts per call, including the first; 1 disables retries
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: u32,
+
+    /// Comma-separated status codes to retry
+    #[arg(long, value_delimiter = ',', value_parser = parse_code, default_value = "unavailable")]
+    pub retry_on: Vec<Code>,
+
+    /// Longest delay before the first retry; each later retry may wait twice as long
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "20ms")]
+    pub retry_backoff: Duration,
+
+    /// Cap on the delay between retries
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    pub retry_max_backoff: Duration,
+
+    /// Retry budget in tokens: failures take one, successes return 0.1, and
+    /// retries pause at half; 0 disables the budget
+    #[arg(long, default_value_t = 10)]
+    pub retry_budget: u32,
+}
+
+impl RetryArgs {
+    /// A policy with a fresh budget.
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            initial_backoff: self.retry_backoff,
+            max_backoff: self.retry_max_backoff,
+            retryable: self.retry_on.clone(),
+            budget: (self.retry_budget > 0).then(|| Arc::new(RetryBudget::new(self.retry_budget))),
+            ..RetryPolicy::default()
+        }
+    }
+}
+
+/// Accepts `unavailable`, `RESOURCE_EXHAUSTED`, `resource-exhausted` or a number.
+pub fn parse_code(s: &str) -> Result<Code, String> {
+    if let Ok(number) = s.parse::<i32>() {
+        return match Code::from_i32(number) {
+            code if code as i32 == number && code != Code::Ok => Ok(code),
+            _ => Err(format!("{} is not a gRPC error code", number)),
+        };
+    }
+    let wanted: String = s.chars().filter(|c| *c != '_' && *c != '-').collect::<String>().to_lowercase();
+    (1..=16)
+        .map(Code::from_i32)
+        .find(|code| format!("{:?}", code).to_lowercase() == wanted)
+        .ok_or_else(|| format!("'{}' is not a gRPC status code like unavailable or internal", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::ZERO,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn pushed_back(code: Code, millis: &str) -> Status {
+        let mut status = Status::new(code, "pushed back");
+        status.metadata_mut().insert(PUSHBACK_METADATA, millis.parse().unwrap());
+        status
+    }
+
+    /// Runs `policy` against attempts that fail with `failures` in order, then succeed.
+    async fn run(policy: &RetryPolicy, failures: Vec<Status>) -> (Result<(), Status>, RetryStats) {
+        let failures = Mutex::new(VecDeque::from(failures));
+        policy
+            .call(|| {
+                let next = failures.lock().unwrap().pop_front();
+                async move { next.map_or(Ok(()), Err) }
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_codes_until_success() {
+        let (result, stats) = run(&policy(4), vec![Status::unavailable("down"), Status::unavailable("down")]).await;
+        assert!(result.is_ok());
+        assert_eq!(stats.attempts, 3);
+        assert_eq!(stats.retries(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_attempts() {
+        let failures = vec![Status::unavailable("down"); 5];
+        let (result, stats) = run(&policy(3), failures).await;
+        assert_eq!(result.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(stats.attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_codes() {
+        let (result, stats) = run(&policy(4), vec![Status::invalid_argument("bad")]).await;
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+        assert_eq!(stats.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn pushback_does_not_make_a_code_retryable() {
+        for code in [Code::InvalidArgument, Code::PermissionDenied] {
+            let (result, stats) = run(&policy(4), vec![pushed_back(code, "0")]).await;
+            assert_eq!(result.unwrap_err().code(), code);
+            assert_eq!((stats.attempts, stats.pushbacks), (1, 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn pushback_sets_the_delay_of_a_retry() {
+        let (result, stats) = run(&policy(4), vec![pushed_back(Code::Unavailable, "1")]).await;
+        assert!(result.is_ok());
+        assert_eq!((stats.attempts, stats.pushbacks), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn negative_or_malformed_pushback_stops_retries() {
+        for millis in ["-1", "soon", "1.5"] {
+            let (result, stats) = run(&policy(4), vec![pushed_back(Code::Unavailable, millis)]).await;
+            assert!(result.is_err(), "{}", millis);
+            assert_eq!(stats.attempts, 1, "{}", millis);
+        }
+    }
+
+    #[test]
+    fn budget_allows_retries_only_above_half() {
+        let budget = RetryBudget::new(4);
+        assert!(budget.allows_retry());
+        budget.failed();
+        assert!(budget.allows_retry());
+        budget.failed();
+        assert!(!budget.allows_retry(), "2 of 4 tokens is not above half");
+        // Ten successes return one token
+        for _ in 0..10 {
+            budget.succeeded();
+        }
+        assert!(budget.allows_retry());
+        for _ in 0..100 {
+            budget.succeeded();
+        }
+        assert_eq!(*budget.tokens.lock().unwrap(), 4.0, "tokens are capped at the maximum");
+    }
+
+    #[tokio::test]
+    async fn budget_throttles_once_exhausted() {
+        let policy = RetryPolicy {
+            budget: Some(Arc::new(RetryBudget::new(4))),
+            ..policy(10)
+        };
+        let (result, stats) = run(&policy, vec![Status::unavailable("down"); 10]).await;
+        assert!(result.is_err());
+        assert_eq!(stats.attempts, 2);
+        assert!(stats.throttled);
+
+        // The shared budget stays drained for the next call
+        let (_, stats) = run(&policy, vec![Status::unavailable("down")]).await;
+        assert_eq!(stats.attempts, 1);
+        assert!(stats.throttled);
+    }
+
+    #[test]
+    fn backoff_grows_and_stays_under_the_cap() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            assert!(policy.backoff(1) <= Duration::from_millis(20));
+            assert!(policy.backoff(3) <= Duration::from_millis(80));
+            assert!(policy.backoff(10) <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn backoff_never_overflows() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        for attempt in [71, 100, 2000, u32::MAX] {
+            assert!(policy.backoff(attempt) <= Duration::from_secs(1));
+        }
+        let unbounded = RetryPolicy {
+            max_backoff: Duration::MAX,
+            multiplier: 1e300,
+            ..policy
+        };
+        unbounded.backoff(u32::MAX);
+    }
+}
@@ -0,0 +1,73 @@
+//! TLS and mutual-TLS options for client `Endpoint`s.
+
+use clap::Args;
+use std::path::{Path, PathBuf};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+/// TLS flags shared by the client and the benchmark.
+///
+/// TLS is used when the server URL is `https://` or any of these flags is set.
+#[derive(Args, Debug, Clone, Default)]
+pub struct TlsArgs {
+    /// PEM CA certificate used to verify the server (defaults to the system roots)
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM client private key for mutual TLS
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Server name to verify instead of the URL host
+    #[arg(long)]
+    pub tls_domain: Option<String>,
+}
+
+impl TlsArgs {
+    pub fn enabled_for(&self, url: &str) -> bool {
+        url.starts_with("https://")
+            || self.tls_ca.is_some()
+            || self.tls_cert.is_some()
+            || self.tls_domain.is_some()
+    }
+
+    /// Reads the PEM files into a `ClientTlsConfig`.
+    pub fn client_tls_config(&self) -> std::io::Result<ClientTlsConfig> {
+        let mut tls = ClientTlsConfig::new();
+        tls = match &self.tls_ca {
+            Some(ca) => tls.ca_certificate(Certificate::from_pem(read_pem(ca)?)),
+            None => tls.with_native_roots(),
+        };
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        if let Some(domain) = &self.tls_domain {
+            tls = tls.domain_name(domain.clone());
+        }
+        Ok(tls)
+    }
+
+    /// Builds an `Endpoint` for `url`, with TLS applied when enabled.
+    pub fn endpoint(&self, url: impl Into<String>) -> Result<Endpoint, Box<dyn std::error::Error + Send + Sync>> {
+        let url = url.into();
+        let tls = self.enabled_for(&url);
+        let url = if tls && url.starts_with("http://") {
+            url.replacen("http://", "https://", 1)
+        } else {
+            url
+        };
+        let endpoint = Endpoint::from_shared(url)?;
+        if tls {
+            Ok(endpoint.tls_config(self.client_tls_config()?)?)
+        } else {
+            Ok(endpoint)
+        }
+    }
+}
+
+fn read_pem(path: &Path) -> std::io::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
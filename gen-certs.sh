@@ -0,0 +1,51 @@
+#!/bin/bash
+# Generate throwaway TLS certificates for local testing.
+#
+# Creates a private CA plus a server certificate (valid for localhost,
+# 127.0.0.1 and ::1) and a client certificate for mutual TLS.
+# NEVER use these outside local development.
+
+set -e
+
+OUT_DIR=${1:-certs}
+DAYS=${DAYS:-30}
+
+if ! command -v openssl >/dev/null 2>&1; then
+    echo "❌ openssl is required to generate certificates"
+    exit 1
+fi
+
+mkdir -p "$OUT_DIR"
+cd "$OUT_DIR"
+
+echo "🔐 Generating test certificates in $OUT_DIR (valid $DAYS days)"
+
+# Certificate authority
+openssl req -x509 -newkey rsa:2048 -nodes -days "$DAYS" \
+    -keyout ca.key -out ca.pem -subj "/CN=grpc-demo test CA" 2>/dev/null
+
+# Server certificate
+openssl req -newkey rsa:2048 -nodes \
+    -keyout server.key -out server.csr -subj "/CN=localhost" 2>/dev/null
+cat > server.ext <<EXT
+subjectAltName = DNS:localhost, IP:127.0.0.1, IP:::1
+extendedKeyUsage = serverAuth
+EXT
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+    -days "$DAYS" -extfile server.ext -out server.pem 2>/dev/null
+
+# Client certificate for mutual TLS
+openssl req -newkey rsa:2048 -nodes \
+    -keyout client.key -out client.csr -subj "/CN=grpc-demo-client" 2>/dev/null
+cat > client.ext <<EXT
+extendedKeyUsage = clientAuth
+EXT
+openssl x509 -req -in client.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+    -days "$DAYS" -extfile client.ext -out client.pem 2>/dev/null
+
+rm -f server.csr client.csr server.ext client.ext ca.srl
+
+echo "✅ Created:"
+echo "   CA:     $OUT_DIR/ca.pem"
+echo "   Server: $OUT_DIR/server.pem, $OUT_DIR/server.key"
+echo "   Client: $OUT_DIR/client.pem, $OUT_DIR/client.key"
@@ -1,7 +1,12 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
-        .compile(&["greet.proto"], &["."])?;
+        // Consumed by the servers' gRPC reflection service
+        .file_descriptor_set_path(out_dir.join("greet_descriptor.bin"))
+        .compile_protos(&["greet.proto"], &["."])?;
     Ok(())
 }
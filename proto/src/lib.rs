@@ -3,3 +3,6 @@ pub mod greet {
 }
 
 pub use greet::*;
+
+/// Encoded `FileDescriptorSet` for `greet.proto`, for gRPC server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("greet_descriptor");
@@ -0,0 +1,20 @@
+//! Background HTTP listeners for the pprof and metrics routers.
+
+use axum::Router;
+use std::net::SocketAddr;
+use tracing::info;
+
+use crate::Shutdown;
+
+/// Binds `addr` and serves `router` from a background task until `shutdown` starts.
+pub async fn spawn(name: &'static str, addr: SocketAddr, router: Router, shutdown: Shutdown) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("🔥 {} HTTP server starting on http://{}", name, addr);
+    tokio::spawn(async move {
+        let serve = axum::serve(listener, router).with_graceful_shutdown(shutdown.triggered());
+        if let Err(e) = serve.await {
+            tracing::error!("{} HTTP server failed: {}", name, e);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,583 @@
+//! Fault injection: a tower layer that fails, delays or cuts off calls so
+//! client resilience and the benchmark's error analysis can be exercised.
+//!
+//! Faults are described by a comma-separated list of rules, each applied to
+//! a call with the probability after `@` (1 when left out):
+//!
+//! | Rule | Does |
+//! |------|------|
+//! | `error=unavailable@0.1` | fails the call with that status code before the handler runs |
+//! | `delay=exp:20ms@0.5` | delays the call by a [`Delay`] before the handler runs |
+//! | `abort=2@0.05` | ends the response with `ABORTED` after that many messages |
+//! | `reset=0@0.01` | resets the HTTP/2 stream (`RST_STREAM`) after that many messages |
+//! | `none` | nothing |
+//!
+//! Injection is off unless rules were configured at startup or it is turned
+//! on through [`Faults::router`], which also replaces the rules at runtime.
+//! While it is on, a call can bring its own rules in the `x-fault` header,
+//! which replace the configured ones for that call. Health checks and
+//! reflection are never faulted.
+
+use crate::metrics::MessageCounter;
+use crate::work::Delay;
+use axum::{
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use rand::Rng;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+use tracing::info;
+
+/// Header (gRPC metadata) that replaces the configured rules for one call.
+pub const FAULT_HEADER: &str = "x-fault";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A set of fault rules; the default injects nothing.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FaultPlan {
+    error: Option<(Code, f64)>,
+    delay: Option<(Delay, f64)>,
+    abort: Option<(u64, f64)>,
+    reset: Option<(u64, f64)>,
+}
+
+impl FaultPlan {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Rolls every rule once for a call.
+    fn roll(&self) -> Injection {
+        let mut rng = rand::thread_rng();
+        let mut hit = |rate: f64| rate >= 1.0 || rng.gen::<f64>() < rate;
+        let mut injection = Injection {
+            error: self.error.filter(|&(_, rate)| hit(rate)).map(|(code, _)| code),
+            delay: self.delay.as_ref().filter(|(_, rate)| hit(*rate)).map(|(delay, _)| delay.sample()),
+            cut: None,
+        };
+        if let Some((after, _)) = self.abort.filter(|&(_, rate)| hit(rate)) {
+            injection.cut = Some(Cut { reset: false, after });
+        }
+        if let Some((after, _)) = self.reset.filter(|&(_, rate)| hit(rate)) {
+            injection.cut = Some(Cut { reset: true, after });
+        }
+        injection
+    }
+}
+
+/// What one call gets.
+struct Injection {
+    error: Option<Code>,
+    delay: Option<Duration>,
+    cut: Option<Cut>,
+}
+
+/// Ends the response after `after` messages, with `ABORTED` or a reset.
+#[derive(Debug, Clone, Copy)]
+struct Cut {
+    reset: bool,
+    after: u64,
+}
+
+/// The rules in force, switchable at runtime, and how often each kind of
+/// fault has been injected.
+#[derive(Debug)]
+pub struct Faults {
+    enabled: AtomicBool,
+    plan: RwLock<FaultPlan>,
+    errors: AtomicU64,
+    delays: AtomicU64,
+    aborts: AtomicU64,
+    resets: AtomicU64,
+}
+
+impl Faults {
+    /// Starts with `plan`, enabled only when it has rules, so clients cannot
+    /// inject faults with `x-fault` into a server nobody set up for it.
+    pub fn new(plan: FaultPlan) -> Arc<Self> {
+        Arc::new(Self {
+            enabled: AtomicBool::new(!plan.is_empty()),
+            plan: RwLock::new(plan),
+            errors: AtomicU64::new(0),
+            delays: AtomicU64::new(0),
+            aborts: AtomicU64::new(0),
+            resets: AtomicU64::new(0),
+        })
+    }
+
+    pub fn layer(self: &Arc<Self>) -> FaultLayer {
+        FaultLayer {
+            faults: Arc::clone(self),
+        }
+    }
+
+    pub fn plan(&self) -> FaultPlan {
+        self.plan.read().unwrap().clone()
+    }
+
+    /// Faults injected so far, of every kind.
+    pub fn injected(&self) -> u64 {
+        [&self.errors, &self.delays, &self.aborts, &self.resets]
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Router for switching faults at runtime. It changes server behaviour
+    /// without authentication, so serve it only on an admin listener:
+    ///
+    /// - `GET /faults` shows the rules, whether they are enabled and the counts
+    /// - `PUT /faults` replaces the rules with the plain-text body, e.g. `error=internal@0.2`
+    /// - `DELETE /faults` removes every rule
+    /// - `POST /faults/enable` and `POST /faults/disable` switch injection,
+    ///   including `x-fault` headers, on and off
+    pub fn router(self: &Arc<Self>) -> Router {
+        let (get_faults, put_faults, delete_faults) = (Arc::clone(self), Arc::clone(self), Arc::clone(self));
+        let (enable, disable) = (Arc::clone(self), Arc::clone(self));
+        Router::new()
+            .route(
+                "/faults",
+                get(move || async move { get_faults.status() })
+                    .put(move |body: String| async move { put_faults.replace(&body) })
+                    .delete(move || async move { delete_faults.set_plan(FaultPlan::default()) }),
+            )
+            .route("/faults/enable", post(move || async move { enable.switch(true) }))
+            .route("/faults/disable", post(move || async move { disable.switch(false) }))
+    }
+
+    fn status(&self) -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "enabled": self.enabled.load(Ordering::Relaxed),
+            "faults": self.plan().to_string(),
+            "injected": {
+                "errors": self.errors.load(Ordering::Relaxed),
+                "delays": self.delays.load(Ordering::Relaxed),
+                "aborts": self.aborts.load(Ordering::Relaxed),
+                "resets": self.resets.load(Ordering::Relaxed),
+            },
+        }))
+    }
+
+    fn replace(&self, spec: &str) -> axum::response::Response {
+        match spec.parse() {
+            Ok(plan) => self.set_plan(plan).into_response(),
+            Err(e) => (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+        }
+    }
+
+    fn set_plan(&self, plan: FaultPlan) -> Json<serde_json::Value> {
+        info!("Fault injection rules set to {}", plan);
+        *self.plan.write().unwrap() = plan;
+        self.status()
+    }
+
+    fn switch(&self, enabled: bool) -> Json<serde_json::Value> {
+        info!("Fault injection {}", if enabled { "enabled" } else { "disabled" });
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.status()
+    }
+
+    /// Decides the faults for a call, from its `x-fault` header or the
+    /// configured rules; errors describe a malformed header.
+    fn for_call<B>(&self, request: &http::Request<B>) -> Result<Option<Injection>, String> {
+        // Health and reflection stay reliable so clients can still probe the server
+        if !self.enabled.load(Ordering::Relaxed) || request.uri().path().starts_with("/grpc.") {
+            return Ok(None);
+        }
+        let injection = match request.headers().get(FAULT_HEADER) {
+            Some(value) => {
+                let plan: FaultPlan = value
+                    .to_str()
+                    .map_err(|_| format!("{} must be ASCII", FAULT_HEADER))
+                    .and_then(str::parse)
+                    .map_err(|e| format!("{}: {}", FAULT_HEADER, e))?;
+                plan.roll()
+            }
+            None => self.plan.read().unwrap().roll(),
+        };
+
+        if injection.error.is_some() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if injection.delay.is_some() {
+            self.delays.fetch_add(1, Ordering::Relaxed);
+        }
+        match injection.cut {
+            Some(Cut { reset: true, .. }) => self.resets.fetch_add(1, Ordering::Relaxed),
+            Some(Cut { reset: false, .. }) => self.aborts.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        };
+        Ok(Some(injection))
+    }
+}
+
+impl FromStr for FaultPlan {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut plan = FaultPlan::default();
+        let spec = spec.trim();
+        if spec == "none" || spec.is_empty() {
+            return Ok(plan);
+        }
+        for rule in spec.split(',').map(str::trim) {
+            let (kind, arg) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a rule like error=unavailable@0.1", rule))?;
+            let (arg, rate) = match arg.rsplit_once('@') {
+                Some((arg, rate)) => (arg, parse_rate(rate)?),
+                None => (arg, 1.0),
+            };
+            let messages = || {
+                arg.parse::<u64>()
+                    .map_err(|_| format!("{} takes a number of messages, not '{}'", kind, arg))
+            };
+            match kind {
+                "error" => plan.error = Some((parse_code(arg)?, rate)),
+                "delay" => plan.delay = Some((arg.parse()?, rate)),
+                "abort" => plan.abort = Some((messages()?, rate)),
+                "reset" => plan.reset = Some((messages()?, rate)),
+                _ => return Err(format!("unknown fault '{}' (expected error, delay, abort or reset)", kind)),
+            }
+        }
+        Ok(plan)
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{}' is not a rate between 0 and 1", s)),
+    }
+}
+
+/// Accepts `unavailable`, `UNAVAILABLE`, `resource_exhausted` or a number.
+fn parse_code(s: &str) -> Result<Code, String> {
+    if let Ok(number) = s.parse::<i32>() {
+        return match Code::from_i32(number) {
+            Code::Ok => Err("error=0 would not be an error".to_string()),
+            code if code as i32 == number => Ok(code),
+            _ => Err(format!("{} is not a gRPC status code", number)),
+        };
+    }
+    let wanted: String = s.chars().filter(|c| *c != '_').collect::<String>().to_lowercase();
+    (1..=16)
+        .map(Code::from_i32)
+        .find(|code| format!("{:?}", code).to_lowercase() == wanted)
+        .ok_or_else(|| format!("'{}' is not a gRPC status code like unavailable or internal", s))
+}
+
+/// `ResourceExhausted` as `resource_exhausted`.
+fn code_name(code: Code) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{:?}", code).chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+impl fmt::Display for FaultPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules = Vec::new();
+        if let Some((code, rate)) = self.error {
+            rules.push(format!("error={}@{}", code_name(code), rate));
+        }
+        if let Some((delay, rate)) = &self.delay {
+            rules.push(format!("delay={}@{}", delay, rate));
+        }
+        if let Some((after, rate)) = self.abort {
+            rules.push(format!("abort={}@{}", after, rate));
+        }
+        if let Some((after, rate)) = self.reset {
+            rules.push(format!("reset={}@{}", after, rate));
+        }
+        if rules.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", rules.join(","))
+        }
+    }
+}
+
+/// Tower layer that injects the faults of a [`Faults`].
+#[derive(Debug, Clone)]
+pub struct FaultLayer {
+    faults: Arc<Faults>,
+}
+
+impl<S> Layer<S> for FaultLayer {
+    type Service = FaultService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FaultService {
+            inner,
+            faults: Arc::clone(&self.faults),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultService<S> {
+    inner: S,
+    faults: Arc<Faults>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for FaultService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = http::Response<FaultBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let injection = match self.faults.for_call(&request) {
+            Ok(Some(injection)) => injection,
+            Ok(None) => {
+                let response = self.inner.call(request);
+                return Box::pin(async move { Ok(response.await?.map(FaultBody::new)) });
+            }
+            Err(e) => return Box::pin(async move { Ok(trailers_only(Status::invalid_argument(e))) }),
+        };
+
+        // The handler has not run yet, so a delay or error here is seen
+        // before any of its work
+        let response = injection.error.is_none().then(|| self.inner.call(request));
+        Box::pin(async move {
+            if let Some(delay) = injection.delay {
+                tokio::time::sleep(delay).await;
+            }
+            match (response, injection.error) {
+                (Some(response), _) => Ok(response.await?.map(|body| FaultBody {
+                    cut: injection.cut,
+                    ..FaultBody::new(body)
+                })),
+                (None, Some(code)) => Ok(trailers_only(Status::new(code, "injected fault"))),
+                (None, None) => unreachable!("the handler is only skipped for an injected error"),
+            }
+        })
+    }
+}
+
+/// A response carrying only `status`, the way gRPC servers send errors.
+fn trailers_only<B>(status: Status) -> http::Response<FaultBody<B>> {
+    let (parts, _) = status.into_http().into_parts();
+    http::Response::from_parts(parts, FaultBody::default())
+}
+
+pin_project_lite::pin_project! {
+    /// Response body that can end early with `ABORTED` or a stream reset.
+    pub struct FaultBody<B> {
+        // `None` for trailers-only responses, which have no body
+        #[pin]
+        inner: Option<B>,
+        cut: Option<Cut>,
+        framing: MessageCounter,
+        messages: u64,
+        done: bool,
+    }
+}
+
+impl<B> FaultBody<B> {
+    fn new(inner: B) -> Self {
+        Self {
+            inner: Some(inner),
+            cut: None,
+            framing: MessageCounter::default(),
+            messages: 0,
+            done: false,
+        }
+    }
+}
+
+/// Empty body, as `TimeoutLayer` substitutes for timed-out calls.
+impl<B> Default for FaultBody<B> {
+    fn default() -> Self {
+        Self {
+            inner: None,
+            cut: None,
+            framing: MessageCounter::default(),
+            messages: 0,
+            done: true,
+        }
+    }
+}
+
+impl<B> Body for FaultBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if let Some(cut) = this.cut.filter(|cut| *this.messages >= cut.after) {
+            *this.done = true;
+            if cut.reset {
+                // hyper answers a failed body with RST_STREAM
+                return Poll::Ready(Some(Err("stream reset by fault injection".into())));
+            }
+            let mut trailers = http::HeaderMap::new();
+            let _ = Status::aborted("stream aborted by fault injection").add_header(&mut trailers);
+            return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+        }
+        let Some(inner) = this.inner.as_pin_mut() else {
+            *this.done = true;
+            return Poll::Ready(None);
+        };
+        match inner.poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    *this.messages += this.framing.feed(data);
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => {
+                *this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || (self.cut.is_none() && self.inner.as_ref().is_none_or(|inner| inner.is_end_stream()))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match (&self.inner, self.cut) {
+            (Some(inner), None) => inner.size_hint(),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str, fault: Option<&str>) -> http::Request<()> {
+        let mut request = http::Request::builder().uri(path);
+        if let Some(spec) = fault {
+            request = request.header(FAULT_HEADER, spec);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn parses_every_rule() {
+        let plan: FaultPlan = "error=resource_exhausted@0.1, delay=exp:20ms@0.5, abort=2@0.05, reset=0".parse().unwrap();
+        assert_eq!(plan.error, Some((Code::ResourceExhausted, 0.1)));
+        assert_eq!(plan.delay, Some((Delay::Exponential { mean: Duration::from_millis(20) }, 0.5)));
+        assert_eq!(plan.abort, Some((2, 0.05)));
+        assert_eq!(plan.reset, Some((0, 1.0)));
+        assert_eq!("error=14".parse::<FaultPlan>().unwrap().error, Some((Code::Unavailable, 1.0)));
+        assert_eq!("error=UNAVAILABLE".parse::<FaultPlan>().unwrap().error, Some((Code::Unavailable, 1.0)));
+        assert!("none".parse::<FaultPlan>().unwrap().is_empty());
+        assert!("".parse::<FaultPlan>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_plan() {
+        for spec in ["none", "error=resource_exhausted@0.1", "delay=lognormal:5ms:1@0.3,abort=3@1,reset=0@0.01"] {
+            let plan: FaultPlan = spec.parse().unwrap();
+            assert_eq!(plan.to_string().parse::<FaultPlan>().unwrap(), plan, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for spec in [
+            "explode=1",
+            "error",
+            "error=ok",
+            "error=0",
+            "error=17",
+            "error=teapot",
+            "error=internal@1.5",
+            "error=internal@-0.1",
+            "error=internal@NaN",
+            "delay=5ms@",
+            "delay=-5ms",
+            "delay=NaNms",
+            "delay=lognormal:5ms:-1",
+            "delay=lognormal:5ms:1e9",
+            "delay=gamma:5ms",
+            "abort=-1",
+            "reset=soon",
+        ] {
+            assert!(spec.parse::<FaultPlan>().is_err(), "{} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn starts_disabled_without_rules() {
+        let faults = Faults::new(FaultPlan::default());
+        assert!(faults.for_call(&request("/greet.GreeterService/SayHello", Some("error=internal"))).unwrap().is_none());
+
+        assert_eq!(faults.switch(true).0["enabled"], true);
+        let injection = faults.for_call(&request("/greet.GreeterService/SayHello", Some("error=internal"))).unwrap();
+        assert_eq!(injection.unwrap().error, Some(Code::Internal));
+        assert_eq!(faults.injected(), 1);
+    }
+
+    #[test]
+    fn starts_enabled_with_configured_rules() {
+        let faults = Faults::new("abort=3".parse().unwrap());
+        let injection = faults.for_call(&request("/greet.GreeterService/SayHello", None)).unwrap().unwrap();
+        assert!(matches!(injection.cut, Some(Cut { reset: false, after: 3 })));
+
+        assert_eq!(faults.switch(false).0["enabled"], false);
+        assert!(faults.for_call(&request("/greet.GreeterService/SayHello", None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn leaves_health_and_reflection_alone() {
+        let faults = Faults::new("error=unavailable".parse().unwrap());
+        assert!(faults.for_call(&request("/grpc.health.v1.Health/Check", None)).unwrap().is_none());
+        let reflection = "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo";
+        assert!(faults.for_call(&request(reflection, Some("error=internal"))).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_a_malformed_header_only_while_enabled() {
+        let faults = Faults::new(FaultPlan::default());
+        assert!(faults.for_call(&request("/greet.GreeterService/SayHello", Some("explode=1"))).is_ok());
+        assert_eq!(faults.switch(true).0["enabled"], true);
+        let error = faults.for_call(&request("/greet.GreeterService/SayHello", Some("explode=1"))).err().unwrap();
+        assert!(error.starts_with(FAULT_HEADER), "{}", error);
+    }
+}
@@ -0,0 +1,334 @@
+use grpc_demo_proto::{greeter_service_server::GreeterService, HelloRequest, HelloResponse};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, instrument};
+
+use crate::recorder::TrafficRecorder;
+use crate::work::WorkModel;
+
+/// CPU iterations burned by every unary call by default.
+pub const UNARY_ITERATIONS: u64 = 50_000;
+/// CPU iterations burned for each streamed message by default.
+pub const STREAM_ITERATIONS: u64 = 25_000;
+
+/// The `GreeterService` implementation shared by all server binaries.
+///
+/// Every server does the same work by default so that benchmark differences
+/// come from transport tuning only; `label` just tags responses and logs.
+#[derive(Debug)]
+pub struct Greeter {
+    label: String,
+    request_count: Arc<AtomicU64>,
+    stream_count: Arc<AtomicU64>,
+    stream_messages: u64,
+    stream_interval: Duration,
+    unary_work: Arc<WorkModel>,
+    /// Done for every message a streaming RPC sends or receives
+    stream_work: Arc<WorkModel>,
+    /// Whether `x-work` may override the models above
+    work_metadata: bool,
+    shutdown: CancellationToken,
+    recorder: Option<TrafficRecorder>,
+}
+
+impl Greeter {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            request_count: Arc::new(AtomicU64::new(0)),
+            stream_count: Arc::new(AtomicU64::new(0)),
+            stream_messages: 5,
+            stream_interval: Duration::from_millis(100),
+            unary_work: Arc::new(WorkModel::cpu(UNARY_ITERATIONS)),
+            stream_work: Arc::new(WorkModel::cpu(STREAM_ITERATIONS)),
+            work_metadata: false,
+            shutdown: CancellationToken::new(),
+            recorder: None,
+        }
+    }
+
+    /// Sets how many messages `SayHelloStream` sends and how far apart.
+    pub fn with_stream_shape(mut self, messages: u64, interval: Duration) -> Self {
+        self.stream_messages = messages;
+        self.stream_interval = interval;
+        self
+    }
+
+    /// Sets the work done per unary call and per streamed message.
+    pub fn with_work(mut self, unary: WorkModel, stream: WorkModel) -> Self {
+        self.unary_work = Arc::new(unary);
+        self.stream_work = Arc::new(stream);
+        self
+    }
+
+    /// Lets a call's `x-work` metadata override the work models; off by
+    /// default, so clients cannot make the server burn CPU or memory.
+    pub fn with_work_metadata(mut self, allowed: bool) -> Self {
+        self.work_metadata = allowed;
+        self
+    }
+
+    /// Ends open streams with `UNAVAILABLE` once `token` is cancelled.
+    pub fn with_shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// Writes every `SayHello` and `SayHelloStream` request to `recorder`.
+    pub fn with_recorder(mut self, recorder: TrafficRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn recorder(&self) -> Option<&TrafficRecorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn stream_messages(&self) -> u64 {
+        self.stream_messages
+    }
+
+    pub fn stream_interval(&self) -> Duration {
+        self.stream_interval
+    }
+
+    pub fn unary_work(&self) -> &WorkModel {
+        &self.unary_work
+    }
+
+    pub fn stream_work(&self) -> &WorkModel {
+        &self.stream_work
+    }
+
+    pub fn work_metadata(&self) -> bool {
+        self.work_metadata
+    }
+
+    /// `default`, unless the call picked its own model with `x-work` and
+    /// that is allowed.
+    fn work_for(&self, metadata: &MetadataMap, default: &Arc<WorkModel>) -> Result<Arc<WorkModel>, String> {
+        if !self.work_metadata {
+            return Ok(default.clone());
+        }
+        Ok(WorkModel::from_metadata(metadata)?.map_or_else(|| default.clone(), Arc::new))
+    }
+
+    /// Unary calls handled so far.
+    pub fn request_count(&self) -> u64 {
+        self.request_count.load(Ordering::Relaxed)
+    }
+
+    /// Streaming calls (server, client and bidirectional) started so far.
+    pub fn stream_count(&self) -> u64 {
+        self.stream_count.load(Ordering::Relaxed)
+    }
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("server is shutting down")
+}
+
+#[tonic::async_trait]
+impl GreeterService for Greeter {
+    #[instrument(skip(self))]
+    async fn say_hello(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> Result<Response<HelloResponse>, Status> {
+        let start_time = Instant::now();
+        let count = self.request_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(recorder) = &self.recorder {
+            recorder.record("unary", &request.get_ref().name, request.metadata());
+        }
+        let work = self.work_for(request.metadata(), &self.unary_work).map_err(Status::invalid_argument)?;
+
+        let name = request.into_inner().name;
+        let sum = work.run(1).await;
+
+        let reply = HelloResponse {
+            message: format!("Hello {} ({} server, request #{}, sum: {})!", name, self.label, count, sum % 1000),
+        };
+
+        let duration = start_time.elapsed();
+        if count.is_multiple_of(100) {
+            info!("{} request #{} completed in {:?}", self.label, count, duration);
+        }
+
+        Ok(Response::new(reply))
+    }
+
+    type SayHelloStreamStream = ReceiverStream<Result<HelloResponse, Status>>;
+
+    #[instrument(skip(self))]
+    async fn say_hello_stream(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> Result<Response<Self::SayHelloStreamStream>, Status> {
+        let start_time = Instant::now();
+        let count = self.stream_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(recorder) = &self.recorder {
+            recorder.record("server-stream", &request.get_ref().name, request.metadata());
+        }
+        let work = self.work_for(request.metadata(), &self.stream_work).map_err(Status::invalid_argument)?;
+
+        let name = request.into_inner().name;
+        let label = self.label.clone();
+        let messages = self.stream_messages;
+        let interval = self.stream_interval;
+        let shutdown = self.shutdown.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+
+        tokio::spawn(async move {
+            for i in 0..messages {
+                if shutdown.is_cancelled() {
+                    let _ = tx.send(Err(shutting_down())).await;
+                    break;
+                }
+                let sum = work.run(i + 1).await;
+
+                let response = HelloResponse {
+                    message: format!("Hello {} ({} server, message #{}, sum: {})!", name, label, i + 1, sum % 1000),
+                };
+
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+
+                if i + 1 < messages {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = shutdown.cancelled() => {}
+                    }
+                }
+            }
+            info!("{} streaming request #{} completed in {:?}", label, count, start_time.elapsed());
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn say_hello_client_stream(
+        &self,
+        request: Request<Streaming<HelloRequest>>,
+    ) -> Result<Response<HelloResponse>, Status> {
+        let start_time = Instant::now();
+        let count = self.stream_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let work = self.work_for(request.metadata(), &self.stream_work).map_err(Status::invalid_argument)?;
+
+        let mut inbound = request.into_inner();
+        let mut names = Vec::new();
+        let mut sum = 0u64;
+        loop {
+            let request = tokio::select! {
+                request = inbound.next() => request,
+                _ = self.shutdown.cancelled() => return Err(shutting_down()),
+            };
+            let Some(request) = request else { break };
+            let request = request?;
+            sum = sum.wrapping_add(work.run(names.len() as u64 + 1).await);
+            names.push(request.name);
+        }
+
+        let reply = HelloResponse {
+            message: format!(
+                "Hello {} ({} server, {} messages, sum: {})!",
+                names.join(", "),
+                self.label,
+                names.len(),
+                sum % 1000
+            ),
+        };
+
+        info!("{} client-streaming request #{} completed in {:?}", self.label, count, start_time.elapsed());
+        Ok(Response::new(reply))
+    }
+
+    type SayHelloChatStream = ReceiverStream<Result<HelloResponse, Status>>;
+
+    #[instrument(skip(self, request))]
+    async fn say_hello_chat(
+        &self,
+        request: Request<Streaming<HelloRequest>>,
+    ) -> Result<Response<Self::SayHelloChatStream>, Status> {
+        let start_time = Instant::now();
+        let count = self.stream_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let work = self.work_for(request.metadata(), &self.stream_work).map_err(Status::invalid_argument)?;
+
+        let mut inbound = request.into_inner();
+        let label = self.label.clone();
+        let shutdown = self.shutdown.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+
+        tokio::spawn(async move {
+            let mut replies = 0u64;
+            loop {
+                let request = tokio::select! {
+                    request = inbound.next() => request,
+                    _ = shutdown.cancelled() => {
+                        let _ = tx.send(Err(shutting_down())).await;
+                        break;
+                    }
+                };
+                let Some(request) = request else { break };
+                let request = match request {
+                    Ok(request) => request,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                replies += 1;
+                let sum = work.run(replies).await;
+
+                let response = HelloResponse {
+                    message: format!("Hello {} ({} server, reply #{}, sum: {})!", request.name, label, replies, sum % 1000),
+                };
+
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+            info!("{} chat #{} completed after {} replies in {:?}", label, count, replies, start_time.elapsed());
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::work::WORK_METADATA;
+
+    fn asking_for(work: &str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(WORK_METADATA, work.parse().unwrap());
+        metadata
+    }
+
+    #[test]
+    fn ignores_x_work_unless_allowed() {
+        let greeter = Greeter::new("test");
+        for work in ["cpu:1", "nap:5ms"] {
+            let picked = greeter.work_for(&asking_for(work), &greeter.unary_work).unwrap();
+            assert_eq!(*picked, WorkModel::cpu(UNARY_ITERATIONS));
+        }
+    }
+
+    #[test]
+    fn allowed_x_work_overrides_the_model() {
+        let greeter = Greeter::new("test").with_work_metadata(true);
+        let picked = greeter.work_for(&asking_for("cpu:1"), &greeter.stream_work).unwrap();
+        assert_eq!(*picked, WorkModel::cpu(1));
+        let default = greeter.work_for(&MetadataMap::new(), &greeter.stream_work).unwrap();
+        assert_eq!(*default, WorkModel::cpu(STREAM_ITERATIONS));
+        let error = greeter.work_for(&asking_for("nap:5ms"), &greeter.stream_work).unwrap_err();
+        assert!(error.starts_with("x-work: unknown step"), "{}", error);
+    }
+}
@@ -0,0 +1,52 @@
+//! `grpc.health.v1.Health` tied to the server lifecycle: NOT_SERVING until
+//! startup completes, and NOT_SERVING again as soon as draining starts.
+
+use crate::{Greeter, Shutdown};
+use grpc_demo_proto::greeter_service_server::GreeterServiceServer;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+/// Handle for flipping the reported status once the server is ready.
+#[derive(Clone)]
+pub struct HealthStatus {
+    reporter: HealthReporter,
+}
+
+impl HealthStatus {
+    /// Reports the server ("") and `greet.GreeterService` as SERVING.
+    pub async fn serving(&self) {
+        self.set(ServingStatus::Serving).await;
+    }
+
+    pub async fn not_serving(&self) {
+        self.set(ServingStatus::NotServing).await;
+    }
+
+    async fn set(&self, status: ServingStatus) {
+        let mut reporter = self.reporter.clone();
+        reporter.set_service_status("", status).await;
+        reporter.set_service_status(greeter_service_name(), status).await;
+    }
+}
+
+fn greeter_service_name() -> &'static str {
+    <GreeterServiceServer<Greeter> as tonic::server::NamedService>::NAME
+}
+
+/// Creates the health service with every status NOT_SERVING, and a task
+/// that reports NOT_SERVING again once `shutdown` triggers.
+pub async fn service(shutdown: &Shutdown) -> (HealthStatus, HealthServer<impl Health>) {
+    let (reporter, service) = tonic_health::server::health_reporter();
+    let status = HealthStatus { reporter };
+    status.not_serving().await;
+
+    let draining = status.clone();
+    let triggered = shutdown.triggered();
+    tokio::spawn(async move {
+        triggered.await;
+        draining.not_serving().await;
+    });
+
+    (status, service)
+}
@@ -0,0 +1,154 @@
+//! Shared pieces of the demo gRPC servers: the greeter service, transport
+//! tuning profiles, layered configuration, graceful shutdown, health
+//! checking, server reflection, Prometheus metrics, fault injection, traffic
+//! recording, synthetic work models and the pprof HTTP router.
+//!
+//! Each binary only picks its defaults and hands them to [`run`].
+
+pub mod admin;
+pub mod config;
+pub mod faults;
+pub mod greeter;
+pub mod health;
+pub mod metrics;
+pub mod profiling;
+pub mod recorder;
+pub mod reflection;
+pub mod shutdown;
+pub mod transport;
+pub mod work;
+
+pub use config::{ConfigError, ServerConfig};
+pub use faults::{FaultPlan, Faults};
+pub use greeter::Greeter;
+pub use metrics::Metrics;
+pub use recorder::TrafficRecorder;
+pub use shutdown::Shutdown;
+pub use transport::{TransportBuilder, TransportProfile, TransportSettings, TunedServer};
+pub use work::WorkModel;
+
+use grpc_demo_proto::greeter_service_server::GreeterServiceServer;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// What sets the server binaries apart; everything else comes from the
+/// layered [`ServerConfig`].
+#[derive(Debug, Clone)]
+pub struct ServerDefaults {
+    /// Greeter name, returned in every reply.
+    pub name: &'static str,
+    /// One line describing the binary in the startup log.
+    pub description: &'static str,
+    pub listen_addr: SocketAddr,
+    pub profile: TransportProfile,
+    pub pprof_addr: Option<SocketAddr>,
+    pub metrics_addr: Option<SocketAddr>,
+    /// `tracing` filter used for the log output.
+    pub log_filter: &'static str,
+}
+
+/// Loads the configuration over `defaults`, serves the greeter, health and
+/// reflection services with metrics and fault injection, and drains on
+/// SIGINT/SIGTERM.
+pub async fn run(defaults: ServerDefaults) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = ServerConfig::new(defaults.listen_addr, defaults.profile);
+    config.pprof_addr = defaults.pprof_addr;
+    config.metrics_addr = defaults.metrics_addr;
+    let config = config.load_or_exit();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(defaults.log_filter)
+        .with_target(false)
+        .init();
+
+    let started = Instant::now();
+    let shutdown = Shutdown::new(config.drain_timeout);
+    shutdown.listen_for_signals();
+
+    let mut greeter = Greeter::new(defaults.name)
+        .with_stream_shape(config.stream_messages, config.stream_interval)
+        .with_work(config.unary_work.clone(), config.stream_work.clone())
+        .with_work_metadata(config.allow_work_metadata)
+        .with_shutdown(shutdown.token());
+    if let Some(path) = &config.record_traffic {
+        greeter = greeter.with_recorder(TrafficRecorder::create(path)?);
+    }
+    let greeter = Arc::new(greeter);
+
+    let metrics = Metrics::new();
+    let faults = Faults::new(config.faults.clone());
+
+    // The pprof server also serves /metrics. /faults changes behaviour, so it
+    // stays off the metrics listener, which scrapers may reach.
+    if let Some(pprof_addr) = config.pprof_addr {
+        let router = profiling::router().merge(metrics.router()).merge(faults.router());
+        admin::spawn("pprof", pprof_addr, router, shutdown.clone()).await?;
+        info!("📊 Profiling dashboard: http://localhost:{}", pprof_addr.port());
+    }
+    if let Some(metrics_addr) = config.metrics_addr {
+        admin::spawn("metrics", metrics_addr, metrics.router(), shutdown.clone()).await?;
+    }
+
+    info!("🚀 {} listening on {} (profile: {})", defaults.description, config.listen_addr, config.profile);
+    info!("🔒 Transport security: {}", config.tls_mode());
+    info!(
+        "📡 Streaming: {} messages per call, {:?} apart",
+        greeter.stream_messages(),
+        greeter.stream_interval()
+    );
+    info!(
+        "⚙️ Work: {} per unary call, {} per streamed message",
+        greeter.unary_work(),
+        greeter.stream_work()
+    );
+    if greeter.work_metadata() {
+        info!("⚙️ Calls may pick their own work with {}", work::WORK_METADATA);
+    }
+    if let Some(recorder) = greeter.recorder() {
+        info!("📼 Recording SayHello/SayHelloStream traffic to {}", recorder.path().display());
+    }
+    if !config.faults.is_empty() {
+        info!("💥 Injecting faults: {}", config.faults);
+    }
+    if config.pprof_addr.is_some() {
+        #[cfg(target_family = "unix")]
+        info!("🔥 pprof ready: CPU profiling and flamegraphs available");
+
+        #[cfg(not(target_family = "unix"))]
+        info!("⚠️  pprof limited: Use Docker for full profiling capabilities");
+    }
+
+    let (health, health_service) = health::service(&shutdown).await;
+    let incoming = config.bind()?;
+    let serve = config
+        .server()?
+        .layer(metrics.layer().timeout(config.transport.timeout))
+        .layer(faults.layer())
+        .add_service(health_service)
+        .add_service(reflection::v1()?)
+        .add_service(reflection::v1alpha()?)
+        .add_service(GreeterServiceServer::from_arc(greeter.clone()))
+        .serve_with_incoming_shutdown(incoming, shutdown.triggered());
+    health.serving().await;
+    if let Some(result) = shutdown.drain(serve).await {
+        result?;
+    }
+
+    info!(
+        "🛑 Shutdown complete after {:?}: {} total requests ({} unary, {} streaming)",
+        started.elapsed(),
+        greeter.request_count() + greeter.stream_count(),
+        greeter.request_count(),
+        greeter.stream_count()
+    );
+    if faults.injected() > 0 {
+        info!("Injected {} faults", faults.injected());
+    }
+    if let Some(recorder) = greeter.recorder().filter(|r| r.dropped() > 0) {
+        warn!("Traffic recording dropped {} requests", recorder.dropped());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,628 @@
+//! Prometheus metrics collected by a tower layer, so every service added to
+//! the server is measured the same way.
+//!
+//! The layer works on raw HTTP/2: the method comes from the request path, the
+//! status from `grpc-status` in the response headers or trailers, and sent
+//! messages are counted from the gRPC length-prefixed framing.
+
+use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use prost::Message;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// Descriptor sets of every service the demo servers register, used to name
+/// methods and tell streaming ones apart from unary ones.
+const DESCRIPTOR_SETS: &[&[u8]] = &[
+    grpc_demo_proto::FILE_DESCRIPTOR_SET,
+    tonic_health::pb::FILE_DESCRIPTOR_SET,
+    tonic_reflection::pb::v1::FILE_DESCRIPTOR_SET,
+    tonic_reflection::pb::v1alpha::FILE_DESCRIPTOR_SET,
+];
+
+/// The server's metric families and the registry they are exported from.
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    handled: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGaugeVec,
+    active_streams: IntGaugeVec,
+    stream_messages: HistogramVec,
+    /// Known method paths, mapped to whether they stream.
+    methods: HashMap<String, bool>,
+}
+
+impl Metrics {
+    /// Metrics that know the methods of every service the demo servers register.
+    pub fn new() -> Arc<Self> {
+        Self::with_descriptor_sets(DESCRIPTOR_SETS)
+    }
+
+    /// Metrics labelled by the methods in the given encoded `FileDescriptorSet`s;
+    /// calls to any other path are recorded as `unknown`.
+    pub fn with_descriptor_sets(descriptor_sets: &[&[u8]]) -> Arc<Self> {
+        let registry = Registry::new();
+        let method = &["method"];
+
+        let requests = IntCounterVec::new(
+            Opts::new("grpc_server_started_total", "RPCs started, by method"),
+            method,
+        )
+        .expect("valid metric");
+        let handled = IntCounterVec::new(
+            Opts::new("grpc_server_handled_total", "RPCs completed, by method and gRPC status code"),
+            &["method", "code"],
+        )
+        .expect("valid metric");
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "grpc_server_handling_seconds",
+                "Time from request to end of response, by method",
+            )
+            .buckets(exponential_buckets(0.0001, 2.0, 18).expect("valid buckets")),
+            method,
+        )
+        .expect("valid metric");
+        let in_flight = IntGaugeVec::new(
+            Opts::new("grpc_server_in_flight_requests", "RPCs currently being handled, by method"),
+            method,
+        )
+        .expect("valid metric");
+        let active_streams = IntGaugeVec::new(
+            Opts::new("grpc_server_active_streams", "Streaming RPCs currently open, by method"),
+            method,
+        )
+        .expect("valid metric");
+        let stream_messages = HistogramVec::new(
+            HistogramOpts::new(
+                "grpc_server_stream_messages_sent",
+                "Messages sent per streaming RPC, by method",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 14).expect("valid buckets")),
+            method,
+        )
+        .expect("valid metric");
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 6] = [
+            Box::new(requests.clone()),
+            Box::new(handled.clone()),
+            Box::new(latency.clone()),
+            Box::new(in_flight.clone()),
+            Box::new(active_streams.clone()),
+            Box::new(stream_messages.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).expect("unique metric");
+        }
+
+        Arc::new(Self {
+            registry,
+            requests,
+            handled,
+            latency,
+            in_flight,
+            active_streams,
+            stream_messages,
+            methods: known_methods(descriptor_sets),
+        })
+    }
+
+    pub fn layer(self: &Arc<Self>) -> MetricsLayer {
+        MetricsLayer {
+            metrics: Arc::clone(self),
+            timeout: None,
+        }
+    }
+
+    /// Router serving `GET /metrics` in the Prometheus text format.
+    pub fn router(self: &Arc<Self>) -> Router {
+        let metrics = Arc::clone(self);
+        Router::new().route("/metrics", get(move || async move { metrics.render() }))
+    }
+
+    /// The registry, for adding application metrics next to the built-in ones.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn render(&self) -> impl IntoResponse {
+        let mut body = Vec::new();
+        let encoder = TextEncoder::new();
+        match encoder.encode(&self.registry.gather(), &mut body) {
+            Ok(()) => (
+                StatusCode::OK,
+                [("content-type", encoder.format_type().to_string())],
+                body,
+            ),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [("content-type", "text/plain".to_string())],
+                format!("Failed to encode metrics: {}", e).into_bytes(),
+            ),
+        }
+    }
+}
+
+fn known_methods(descriptor_sets: &[&[u8]]) -> HashMap<String, bool> {
+    let mut methods = HashMap::new();
+    for encoded in descriptor_sets {
+        let Ok(set) = prost_types::FileDescriptorSet::decode(*encoded) else {
+            tracing::warn!("Ignoring undecodable descriptor set in metrics");
+            continue;
+        };
+        for file in set.file {
+            let package = file.package();
+            for service in &file.service {
+                let service_name = if package.is_empty() {
+                    service.name().to_string()
+                } else {
+                    format!("{}.{}", package, service.name())
+                };
+                for method in &service.method {
+                    let streaming = method.client_streaming() || method.server_streaming();
+                    methods.insert(format!("/{}/{}", service_name, method.name()), streaming);
+                }
+            }
+        }
+    }
+    methods
+}
+
+fn grpc_code(headers: &http::HeaderMap) -> Option<tonic::Code> {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i32>().ok())
+        .map(tonic::Code::from_i32)
+}
+
+/// The `grpc-timeout` request header: an integer of at most 8 digits followed
+/// by a unit, `H`, `M`, `S`, `m`, `u` or `n`.
+fn grpc_timeout(headers: &http::HeaderMap) -> Option<Duration> {
+    let value = headers.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// The canonical name of a status code, as other gRPC implementations label it.
+fn code_name(code: tonic::Code) -> &'static str {
+    use tonic::Code::*;
+    match code {
+        Ok => "OK",
+        Cancelled => "CANCELLED",
+        Unknown => "UNKNOWN",
+        InvalidArgument => "INVALID_ARGUMENT",
+        DeadlineExceeded => "DEADLINE_EXCEEDED",
+        NotFound => "NOT_FOUND",
+        AlreadyExists => "ALREADY_EXISTS",
+        PermissionDenied => "PERMISSION_DENIED",
+        ResourceExhausted => "RESOURCE_EXHAUSTED",
+        FailedPrecondition => "FAILED_PRECONDITION",
+        Aborted => "ABORTED",
+        OutOfRange => "OUT_OF_RANGE",
+        Unimplemented => "UNIMPLEMENTED",
+        Internal => "INTERNAL",
+        Unavailable => "UNAVAILABLE",
+        DataLoss => "DATA_LOSS",
+        Unauthenticated => "UNAUTHENTICATED",
+    }
+}
+
+/// Tower layer that records [`Metrics`] for every call passing through it.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    timeout: Option<Duration>,
+}
+
+impl MetricsLayer {
+    /// The server timeout applied outside this layer. Calls dropped before
+    /// their response once it (or a shorter `grpc-timeout`) has passed are
+    /// counted as `DEADLINE_EXCEEDED` rather than `CANCELLED`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: Arc::clone(&self.metrics),
+            timeout: self.timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+    timeout: Option<Duration>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+{
+    type Response = http::Response<MetricsBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let mut call = CallRecord::start(Arc::clone(&self.metrics), request.uri().path());
+        // The server applies the shorter of its own timeout and the client's
+        call.deadline = match (self.timeout, grpc_timeout(request.headers())) {
+            (Some(server), Some(client)) => Some(server.min(client)),
+            (server, client) => server.or(client),
+        };
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = match response.await {
+                Ok(response) => response,
+                Err(e) => {
+                    call.code = Some(tonic::Code::Unknown);
+                    drop(call);
+                    return Err(e);
+                }
+            };
+            // Timeouts only cut off the wait for the response, not the body
+            call.deadline = None;
+            let (parts, body) = response.into_parts();
+            // Trailers-only responses (most errors) carry the status in the headers
+            call.code = grpc_code(&parts.headers);
+            Ok(http::Response::from_parts(
+                parts,
+                MetricsBody {
+                    inner: body,
+                    call: Some(call),
+                    framing: MessageCounter::default(),
+                },
+            ))
+        })
+    }
+}
+
+/// Per-call bookkeeping, finalized exactly once when the response body ends
+/// or is dropped (a dropped, unfinished body means the client went away, unless
+/// the call outlived its deadline while waiting for the response).
+struct CallRecord {
+    metrics: Arc<Metrics>,
+    method: String,
+    streaming: bool,
+    started: Instant,
+    code: Option<tonic::Code>,
+    deadline: Option<Duration>,
+    messages: u64,
+    finished: bool,
+}
+
+impl CallRecord {
+    fn start(metrics: Arc<Metrics>, path: &str) -> Self {
+        // Only known paths become labels, so clients cannot grow the series count
+        let (method, streaming) = match metrics.methods.get(path) {
+            Some(&streaming) => (path.to_string(), streaming),
+            None => ("unknown".to_string(), false),
+        };
+        metrics.requests.with_label_values(&[&method]).inc();
+        metrics.in_flight.with_label_values(&[&method]).inc();
+        if streaming {
+            metrics.active_streams.with_label_values(&[&method]).inc();
+        }
+        Self {
+            metrics,
+            method,
+            streaming,
+            started: Instant::now(),
+            code: None,
+            deadline: None,
+            messages: 0,
+            finished: false,
+        }
+    }
+}
+
+impl Drop for CallRecord {
+    fn drop(&mut self) {
+        let m = &self.metrics;
+        let code = match (self.code, self.finished) {
+            (Some(code), _) => code,
+            (None, true) => tonic::Code::Unknown,
+            (None, false) => match self.deadline {
+                Some(deadline) if self.started.elapsed() >= deadline => tonic::Code::DeadlineExceeded,
+                _ => tonic::Code::Cancelled,
+            },
+        };
+        m.handled.with_label_values(&[&self.method, code_name(code)]).inc();
+        m.latency
+            .with_label_values(&[&self.method])
+            .observe(self.started.elapsed().as_secs_f64());
+        m.in_flight.with_label_values(&[&self.method]).dec();
+        if self.streaming {
+            m.active_streams.with_label_values(&[&self.method]).dec();
+            m.stream_messages
+                .with_label_values(&[&self.method])
+                .observe(self.messages as f64);
+        }
+    }
+}
+
+/// Counts gRPC messages in a byte stream of 5-byte-prefixed frames.
+#[derive(Default)]
+pub(crate) struct MessageCounter {
+    header: [u8; 5],
+    header_len: usize,
+    remaining: usize,
+}
+
+impl MessageCounter {
+    /// Feeds a chunk and returns how many new messages started in it.
+    pub(crate) fn feed(&mut self, mut chunk: &[u8]) -> u64 {
+        let mut started = 0;
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(chunk.len());
+                self.remaining -= n;
+                chunk = &chunk[n..];
+                continue;
+            }
+            let n = (5 - self.header_len).min(chunk.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&chunk[..n]);
+            self.header_len += n;
+            chunk = &chunk[n..];
+            if self.header_len == 5 {
+                started += 1;
+                self.header_len = 0;
+                self.remaining = u32::from_be_bytes([self.header[1], self.header[2], self.header[3], self.header[4]]) as usize;
+            }
+        }
+        started
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Response body wrapper that counts messages and captures the final status.
+    pub struct MetricsBody<B> {
+        #[pin]
+        inner: B,
+        call: Option<CallRecord>,
+        framing: MessageCounter,
+    }
+}
+
+/// Empty, unrecorded body, as `TimeoutLayer` substitutes for timed-out calls.
+impl<B: Default> Default for MetricsBody<B> {
+    fn default() -> Self {
+        Self {
+            inner: B::default(),
+            call: None,
+            framing: MessageCounter::default(),
+        }
+    }
+}
+
+impl<B> Body for MetricsBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let polled = this.inner.poll_frame(cx);
+        let Some(call) = this.call.as_mut() else {
+            return polled;
+        };
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    call.messages += this.framing.feed(data.chunk());
+                } else if let Some(trailers) = frame.trailers_ref() {
+                    if let Some(code) = grpc_code(trailers) {
+                        call.code = Some(code);
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                call.code.get_or_insert(tonic::Code::Internal);
+                call.finished = true;
+            }
+            Poll::Ready(None) => call.finished = true,
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+    use tower_http::timeout::TimeoutLayer;
+
+    const SAY_HELLO: &str = "/greet.GreeterService/SayHello";
+
+    fn handled(metrics: &Metrics, code: &str) -> u64 {
+        metrics.handled.with_label_values(&[SAY_HELLO, code]).get()
+    }
+
+    fn headers(timeout: &str) -> http::HeaderMap {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("grpc-timeout", timeout.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn labels_codes_with_their_canonical_names() {
+        let metrics = Metrics::new();
+        for code in [tonic::Code::Ok, tonic::Code::Unavailable, tonic::Code::InvalidArgument] {
+            let mut call = CallRecord::start(Arc::clone(&metrics), SAY_HELLO);
+            call.code = Some(code);
+        }
+        assert_eq!(handled(&metrics, "OK"), 1);
+        assert_eq!(handled(&metrics, "UNAVAILABLE"), 1);
+        assert_eq!(handled(&metrics, "INVALID_ARGUMENT"), 1);
+        assert_eq!(handled(&metrics, "Ok"), 0);
+    }
+
+    #[test]
+    fn an_abandoned_call_is_cancelled_until_its_deadline_passes() {
+        let metrics = Metrics::new();
+        let mut call = CallRecord::start(Arc::clone(&metrics), SAY_HELLO);
+        call.deadline = Some(Duration::from_secs(60));
+        drop(call);
+        assert_eq!(handled(&metrics, "CANCELLED"), 1);
+
+        let mut call = CallRecord::start(Arc::clone(&metrics), SAY_HELLO);
+        call.deadline = Some(Duration::ZERO);
+        drop(call);
+        assert_eq!(handled(&metrics, "DEADLINE_EXCEEDED"), 1);
+        assert_eq!(handled(&metrics, "CANCELLED"), 1);
+    }
+
+    #[test]
+    fn a_finished_call_without_a_status_is_unknown() {
+        let metrics = Metrics::new();
+        let mut call = CallRecord::start(Arc::clone(&metrics), SAY_HELLO);
+        call.deadline = Some(Duration::ZERO);
+        call.finished = true;
+        drop(call);
+        assert_eq!(handled(&metrics, "UNKNOWN"), 1);
+    }
+
+    #[tokio::test]
+    async fn a_call_cut_off_by_the_server_timeout_exceeds_its_deadline() {
+        let metrics = Metrics::new();
+        let timeout = Duration::from_millis(10);
+        let service = tower::ServiceBuilder::new()
+            .layer(TimeoutLayer::new(timeout))
+            .layer(metrics.layer().timeout(Some(timeout)))
+            .service_fn(|_: http::Request<axum::body::Body>| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok::<_, std::convert::Infallible>(http::Response::new(axum::body::Body::empty()))
+            });
+        let request = http::Request::builder().uri(SAY_HELLO).body(axum::body::Body::empty()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(handled(&metrics, "DEADLINE_EXCEEDED"), 1);
+        assert_eq!(handled(&metrics, "CANCELLED"), 0);
+    }
+
+    #[test]
+    fn parses_grpc_timeout_units() {
+        assert_eq!(grpc_timeout(&headers("2H")), Some(Duration::from_secs(7200)));
+        assert_eq!(grpc_timeout(&headers("3M")), Some(Duration::from_secs(180)));
+        assert_eq!(grpc_timeout(&headers("5S")), Some(Duration::from_secs(5)));
+        assert_eq!(grpc_timeout(&headers("250m")), Some(Duration::from_millis(250)));
+        assert_eq!(grpc_timeout(&headers("99999999u")), Some(Duration::from_micros(99_999_999)));
+        assert_eq!(grpc_timeout(&headers("0n")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn ignores_malformed_grpc_timeouts() {
+        for value in ["", "S", "10", "10s", "-1S", "1.5S", "123456789S"] {
+            assert_eq!(grpc_timeout(&headers(value)), None, "{:?}", value);
+        }
+        assert_eq!(grpc_timeout(&http::HeaderMap::new()), None);
+    }
+
+    /// One gRPC message: an uncompressed flag, a big-endian length and the payload.
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut framed = vec![0];
+        framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        framed.extend_from_slice(payload);
+        framed
+    }
+
+    #[test]
+    fn counts_one_frame_per_chunk() {
+        let mut counter = MessageCounter::default();
+        assert_eq!(counter.feed(&frame(b"hello")), 1);
+        assert_eq!(counter.feed(&frame(b"world")), 1);
+    }
+
+    #[test]
+    fn counts_several_frames_in_one_chunk() {
+        let chunk = [frame(b"a"), frame(b"bc"), frame(b"def")].concat();
+        assert_eq!(MessageCounter::default().feed(&chunk), 3);
+    }
+
+    #[test]
+    fn counts_a_payload_split_across_chunks_once() {
+        let framed = frame(&[7; 100]);
+        let mut counter = MessageCounter::default();
+        assert_eq!(counter.feed(&framed[..40]), 1);
+        assert_eq!(counter.feed(&framed[40..80]), 0);
+        assert_eq!(counter.feed(&framed[80..]), 0);
+        assert_eq!(counter.feed(&frame(b"next")), 1);
+    }
+
+    #[test]
+    fn counts_zero_length_messages() {
+        let chunk = [frame(b""), frame(b""), frame(b"x"), frame(b"")].concat();
+        assert_eq!(MessageCounter::default().feed(&chunk), 4);
+    }
+
+    #[test]
+    fn counts_a_header_split_at_every_offset() {
+        let stream = [frame(b"first"), frame(b""), frame(b"third")].concat();
+        for split in 0..=5 {
+            let mut counter = MessageCounter::default();
+            let started = counter.feed(&stream[..split]) + counter.feed(&stream[split..]);
+            assert_eq!(started, 3, "header split after {} bytes", split);
+        }
+    }
+
+    #[test]
+    fn counts_a_stream_fed_one_byte_at_a_time() {
+        let stream = [frame(b"one"), frame(b""), frame(&[1; 300])].concat();
+        let mut counter = MessageCounter::default();
+        let started: u64 = stream.chunks(1).map(|byte| counter.feed(byte)).sum();
+        assert_eq!(started, 3);
+    }
+}
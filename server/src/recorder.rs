@@ -0,0 +1,133 @@
+//! Records incoming `SayHello` and `SayHelloStream` requests as JSON lines
+//! that the benchmark's `--replay` sends again with the original timing:
+//!
+//! ```json
+//! {"offset_ms":1520.25,"rpc":"unary","name":"alice","metadata":{"x-tenant":"a"}}
+//! ```
+//!
+//! `offset_ms` counts from when recording started. Lines are written by a
+//! background thread so a slow disk never delays a call; when it falls too
+//! far behind, lines are dropped and counted instead.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Instant;
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
+use tracing::{error, warn};
+
+/// Lines buffered between the handlers and the writer thread.
+const QUEUE_LINES: usize = 65_536;
+
+/// Set by the HTTP/2 client or tonic itself rather than by the caller, or
+/// credentials that must not end up in a file.
+const SKIPPED_METADATA: &[&str] = &[
+    "te",
+    "content-type",
+    "user-agent",
+    "grpc-accept-encoding",
+    "grpc-encoding",
+    "grpc-timeout",
+    "authorization",
+    "cookie",
+];
+
+#[derive(Serialize)]
+struct RecordedRequest<'a> {
+    offset_ms: f64,
+    rpc: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<&'a str, &'a str>,
+}
+
+/// Cheap to clone; every clone appends to the same file.
+#[derive(Debug, Clone)]
+pub struct TrafficRecorder {
+    path: Arc<PathBuf>,
+    started: Instant,
+    lines: SyncSender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl TrafficRecorder {
+    /// Truncates `path` and starts the writer thread.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot record traffic to {}: {}", path.display(), e)))?;
+        let (lines, queue) = mpsc::sync_channel::<String>(QUEUE_LINES);
+        let shown = path.display().to_string();
+        std::thread::Builder::new()
+            .name("traffic-recorder".into())
+            .spawn(move || {
+                let mut out = BufWriter::new(file);
+                // Flushing whenever the queue runs dry batches writes under
+                // load and keeps the file current when traffic is light.
+                let result = (|| {
+                    while let Ok(line) = queue.recv() {
+                        out.write_all(line.as_bytes())?;
+                        while let Ok(line) = queue.try_recv() {
+                            out.write_all(line.as_bytes())?;
+                        }
+                        out.flush()?;
+                    }
+                    Ok::<_, io::Error>(())
+                })();
+                if let Err(e) = result {
+                    error!("Traffic recording to {} stopped: {}", shown, e);
+                }
+            })?;
+
+        Ok(Self {
+            path: Arc::new(path.to_path_buf()),
+            started: Instant::now(),
+            lines,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Lines lost because the writer fell behind or stopped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queues one line for a call of `rpc` (`unary` or `server-stream`).
+    pub fn record(&self, rpc: &str, name: &str, metadata: &MetadataMap) {
+        let metadata = metadata
+            .iter()
+            .filter_map(|entry| match entry {
+                KeyAndValueRef::Ascii(key, value) => Some((key.as_str(), value.to_str().ok()?)),
+                KeyAndValueRef::Binary(..) => None,
+            })
+            .filter(|(key, _)| !SKIPPED_METADATA.contains(key))
+            .collect();
+        let line = RecordedRequest {
+            offset_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            rpc,
+            name,
+            metadata,
+        };
+        let Ok(mut line) = serde_json::to_string(&line) else {
+            return;
+        };
+        line.push('\n');
+
+        match self.lines.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("Traffic recorder for {} is behind; dropping lines", self.path.display());
+                }
+            }
+        }
+    }
+}
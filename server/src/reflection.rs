@@ -0,0 +1,20 @@
+//! gRPC server reflection (v1 and v1alpha) so generic tools such as grpcurl
+//! can discover `greet.GreeterService` without the .proto files.
+
+use tonic_reflection::server::{v1, v1alpha, Builder, Error};
+
+fn builder() -> Builder<'static> {
+    Builder::configure()
+        .register_encoded_file_descriptor_set(grpc_demo_proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+}
+
+/// The `grpc.reflection.v1.ServerReflection` service.
+pub fn v1() -> Result<v1::ServerReflectionServer<impl v1::ServerReflection>, Error> {
+    builder().build_v1()
+}
+
+/// The `grpc.reflection.v1alpha.ServerReflection` service, for older clients.
+pub fn v1alpha() -> Result<v1alpha::ServerReflectionServer<impl v1alpha::ServerReflection>, Error> {
+    builder().build_v1alpha()
+}
@@ -0,0 +1,94 @@
+//! Signal-driven graceful shutdown with a drain deadline.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Coordinates draining: the token is cancelled on SIGINT/SIGTERM, which
+/// stops accepting connections (tonic sends GOAWAY), ends stream producers,
+/// and starts the drain deadline.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    token: CancellationToken,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(drain_timeout: Duration) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            drain_timeout,
+        }
+    }
+
+    /// Token cancelled once draining starts.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Starts draining without waiting for a signal.
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    /// Resolves once draining has started; pass to `serve_with_shutdown`.
+    pub fn triggered(&self) -> impl Future<Output = ()> + Send + 'static {
+        let token = self.token.clone();
+        async move { token.cancelled().await }
+    }
+
+    /// Spawns a task that starts draining on the first SIGINT or SIGTERM.
+    pub fn listen_for_signals(&self) {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            let signal = wait_for_signal().await;
+            info!("🛑 Received {}, draining connections", signal);
+            token.cancel();
+        });
+    }
+
+    /// Drives `serve` to completion, but gives up once the drain deadline has
+    /// passed after shutdown was triggered. Returns `None` on timeout.
+    pub async fn drain<F: Future>(&self, serve: F) -> Option<F::Output> {
+        tokio::pin!(serve);
+        tokio::select! {
+            output = &mut serve => return Some(output),
+            _ = self.token.cancelled() => {}
+        }
+        match tokio::time::timeout(self.drain_timeout, serve).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                warn!("Drain deadline of {:?} passed, closing remaining connections", self.drain_timeout);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = sigterm.recv() => "SIGTERM",
+        },
+        Err(e) => {
+            warn!("Cannot listen for SIGTERM ({}), only handling Ctrl+C", e);
+            let _ = tokio::signal::ctrl_c().await;
+            "SIGINT"
+        }
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl+C"
+}
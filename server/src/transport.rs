@@ -0,0 +1,208 @@
+//! Transport tuning profiles for `tonic::transport::Server`.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::transport::Server;
+use tower::layer::util::{Identity, Stack};
+use tower::util::{option_layer, Either};
+use tower_http::timeout::TimeoutLayer;
+
+/// A `Server` with the tuning from a [`TransportBuilder`] applied, ready for
+/// further `.layer()` / `.add_service()` calls.
+pub type TunedServer = Server<Stack<Either<TimeoutLayer, Identity>, Identity>>;
+
+/// The named tuning presets used by the demo binaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportProfile {
+    /// Tonic defaults, no connection pooling optimizations.
+    Baseline,
+    /// Keepalive, no-delay and 1MB windows for moderate concurrency.
+    Pooled,
+    /// Large windows, HTTP/2 keepalive and very high stream limits.
+    HighConcurrency,
+}
+
+impl TransportProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransportProfile::Baseline => "baseline",
+            TransportProfile::Pooled => "pooled",
+            TransportProfile::HighConcurrency => "high-concurrency",
+        }
+    }
+
+    pub fn settings(&self) -> TransportSettings {
+        match self {
+            TransportProfile::Baseline => TransportSettings::default(),
+            TransportProfile::Pooled => TransportSettings {
+                tcp_keepalive: Some(Duration::from_secs(600)),
+                tcp_nodelay: true,
+                timeout: Some(Duration::from_secs(30)),
+                concurrency_limit_per_connection: Some(256),
+                initial_stream_window_size: Some(1024 * 1024),
+                initial_connection_window_size: Some(1024 * 1024),
+                max_concurrent_streams: Some(1000),
+                ..TransportSettings::default()
+            },
+            TransportProfile::HighConcurrency => TransportSettings {
+                tcp_keepalive: Some(Duration::from_secs(600)), // 10 minutes keepalive
+                tcp_nodelay: true, // Disable Nagle's algorithm for lower latency
+                timeout: Some(Duration::from_secs(120)), // Longer timeout for reliability
+                concurrency_limit_per_connection: Some(10000), // Much higher limit to prevent rejections
+                initial_stream_window_size: Some(16 * 1024 * 1024), // 16MB initial window for better flow control
+                initial_connection_window_size: Some(16 * 1024 * 1024), // 16MB connection window
+                max_concurrent_streams: Some(10000), // Much higher stream limit
+                http2_keepalive_interval: Some(Duration::from_secs(60)), // HTTP/2 keepalive
+                http2_keepalive_timeout: Some(Duration::from_secs(20)), // HTTP/2 keepalive timeout
+                http2_adaptive_window: Some(true), // Enable adaptive flow control
+            },
+        }
+    }
+}
+
+impl fmt::Display for TransportProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TransportProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "baseline" => Ok(TransportProfile::Baseline),
+            "pooled" => Ok(TransportProfile::Pooled),
+            "high-concurrency" => Ok(TransportProfile::HighConcurrency),
+            other => Err(format!(
+                "unknown transport profile '{}' (expected baseline, pooled or high-concurrency)",
+                other
+            )),
+        }
+    }
+}
+
+/// Every `Server::builder()` knob the profiles touch. `None` leaves the tonic default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransportSettings {
+    pub tcp_keepalive: Option<Duration>,
+    pub tcp_nodelay: bool,
+    /// Applied both as the server timeout and as a `TimeoutLayer`.
+    pub timeout: Option<Duration>,
+    pub concurrency_limit_per_connection: Option<usize>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub max_concurrent_streams: Option<u32>,
+    pub http2_keepalive_interval: Option<Duration>,
+    pub http2_keepalive_timeout: Option<Duration>,
+    pub http2_adaptive_window: Option<bool>,
+}
+
+/// Builds a [`TunedServer`] from a profile, with optional per-knob overrides.
+///
+/// ```no_run
+/// # use grpc_demo_server::{TransportBuilder, TransportProfile};
+/// # use std::time::Duration;
+/// let server = TransportBuilder::new(TransportProfile::Pooled)
+///     .timeout(Some(Duration::from_secs(10)))
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TransportBuilder {
+    profile: TransportProfile,
+    settings: TransportSettings,
+}
+
+impl TransportBuilder {
+    pub fn new(profile: TransportProfile) -> Self {
+        Self {
+            profile,
+            settings: profile.settings(),
+        }
+    }
+
+    pub fn profile(&self) -> TransportProfile {
+        self.profile
+    }
+
+    pub fn settings(&self) -> &TransportSettings {
+        &self.settings
+    }
+
+    /// Replaces every knob at once, e.g. with settings resolved from config.
+    pub fn with_settings(mut self, settings: TransportSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.settings.tcp_keepalive = keepalive;
+        self
+    }
+
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.settings.tcp_nodelay = enabled;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.settings.timeout = timeout;
+        self
+    }
+
+    pub fn concurrency_limit_per_connection(mut self, limit: Option<usize>) -> Self {
+        self.settings.concurrency_limit_per_connection = limit;
+        self
+    }
+
+    pub fn initial_stream_window_size(mut self, size: Option<u32>) -> Self {
+        self.settings.initial_stream_window_size = size;
+        self
+    }
+
+    pub fn initial_connection_window_size(mut self, size: Option<u32>) -> Self {
+        self.settings.initial_connection_window_size = size;
+        self
+    }
+
+    pub fn max_concurrent_streams(mut self, max: Option<u32>) -> Self {
+        self.settings.max_concurrent_streams = max;
+        self
+    }
+
+    pub fn http2_keepalive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.settings.http2_keepalive_interval = interval;
+        self
+    }
+
+    pub fn http2_keepalive_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.settings.http2_keepalive_timeout = timeout;
+        self
+    }
+
+    pub fn http2_adaptive_window(mut self, enabled: Option<bool>) -> Self {
+        self.settings.http2_adaptive_window = enabled;
+        self
+    }
+
+    pub fn build(&self) -> TunedServer {
+        let s = &self.settings;
+        let mut server = Server::builder()
+            .tcp_keepalive(s.tcp_keepalive)
+            .tcp_nodelay(s.tcp_nodelay)
+            .initial_stream_window_size(s.initial_stream_window_size)
+            .initial_connection_window_size(s.initial_connection_window_size)
+            .max_concurrent_streams(s.max_concurrent_streams)
+            .http2_keepalive_interval(s.http2_keepalive_interval)
+            .http2_keepalive_timeout(s.http2_keepalive_timeout)
+            .http2_adaptive_window(s.http2_adaptive_window);
+        if let Some(timeout) = s.timeout {
+            server = server.timeout(timeout);
+        }
+        if let Some(limit) = s.concurrency_limit_per_connection {
+            server = server.concurrency_limit_per_connection(limit);
+        }
+        server.layer(option_layer(s.timeout.map(TimeoutLayer::new)))
+    }
+}
@@ -0,0 +1,438 @@
+//! Synthetic work the greeter does per call, so profiles can show realistic
+//! service shapes instead of one tight loop.
+//!
+//! A model is a `+`-separated list of steps run in order:
+//!
+//! | Step | Does |
+//! |------|------|
+//! | `cpu:50000` | spins the CPU for that many iterations on the async worker |
+//! | `sleep:<delay>` | awaits a timer, like a call to another service |
+//! | `blocking:<delay>` | sleeps a thread from the `spawn_blocking` pool, like synchronous I/O |
+//! | `alloc:64KiB` | allocates and touches that many bytes, held until the call's work ends |
+//! | `none` | nothing |
+//!
+//! A `<delay>` is `5ms` (fixed), `uniform:1ms-9ms`, `exp:5ms` (exponential
+//! with that mean) or `lognormal:5ms:0.5` (median and sigma). For example
+//! `cpu:20000+sleep:lognormal:4ms:0.8+alloc:1MiB`.
+//!
+//! A whole model does at most a billion CPU iterations, allocates at most
+//! 1 GiB, has at most 32 steps and waits at most a minute in total.
+//!
+//! When the server allows it, clients can pick the model for one call with
+//! the `x-work` metadata key.
+
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::metadata::MetadataMap;
+
+/// Metadata key that overrides the configured model for one call.
+pub const WORK_METADATA: &str = "x-work";
+
+/// Caps that keep a mistyped (or hostile) `x-work` from hanging the server,
+/// per step and for the whole model.
+const MAX_ITERATIONS: u64 = 1_000_000_000;
+const MAX_DELAY: Duration = Duration::from_secs(60);
+const MAX_ALLOC: usize = 1 << 30;
+const MAX_STEPS: usize = 32;
+/// Wider lognormals put nearly every sample at 0 or at `MAX_DELAY`.
+const MAX_SIGMA: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WorkModel {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Cpu(u64),
+    Sleep(Delay),
+    Blocking(Delay),
+    Alloc(usize),
+}
+
+/// A latency distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delay {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    Exponential { mean: Duration },
+    LogNormal { median: Duration, sigma: f64 },
+}
+
+impl Delay {
+    pub fn sample(&self) -> Duration {
+        let mut rng = rand::thread_rng();
+        match *self {
+            Delay::Fixed(delay) => delay.min(MAX_DELAY),
+            Delay::Uniform(low, high) => rng.gen_range(low..=high).min(MAX_DELAY),
+            // Inverse transform; 1 - U avoids ln(0)
+            Delay::Exponential { mean } => scaled(mean, -(1.0 - rng.gen::<f64>()).ln()),
+            Delay::LogNormal { median, sigma } => scaled(median, (sigma * standard_normal(&mut rng)).exp()),
+        }
+    }
+}
+
+/// `base * factor`, capped at `MAX_DELAY` before it can overflow a `Duration`.
+fn scaled(base: Duration, factor: f64) -> Duration {
+    if base.is_zero() {
+        return base;
+    }
+    let factor = factor.min(MAX_DELAY.as_secs_f64() / base.as_secs_f64());
+    Duration::try_from_secs_f64(base.as_secs_f64() * factor)
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY)
+}
+
+/// Box-Muller transform.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+impl WorkModel {
+    /// Spins the CPU for `iterations`, the greeter's original workload.
+    pub fn cpu(iterations: u64) -> Self {
+        Self {
+            steps: vec![Step::Cpu(iterations)],
+        }
+    }
+
+    /// The model a call asked for with `x-work`, if it did.
+    pub fn from_metadata(metadata: &MetadataMap) -> Result<Option<Self>, String> {
+        let Some(value) = metadata.get(WORK_METADATA) else {
+            return Ok(None);
+        };
+        let spec = value.to_str().map_err(|_| format!("{} must be ASCII", WORK_METADATA))?;
+        spec.parse().map(Some).map_err(|e| format!("{}: {}", WORK_METADATA, e))
+    }
+
+    /// Runs every step in order. Returns a checksum of the CPU work so it
+    /// cannot be optimised away; `factor` varies it between messages.
+    pub async fn run(&self, factor: u64) -> u64 {
+        let mut sum = 0u64;
+        let mut held = Vec::new();
+        // Every delay step draws from the same budget of `MAX_DELAY`
+        let mut waited = Duration::ZERO;
+        let mut sample = |delay: &Delay| {
+            let delay = delay.sample().min(MAX_DELAY - waited);
+            waited += delay;
+            delay
+        };
+        for step in &self.steps {
+            match step {
+                Step::Cpu(iterations) => sum = sum.wrapping_add(cpu_work(*iterations, factor)),
+                Step::Sleep(delay) => tokio::time::sleep(sample(delay)).await,
+                Step::Blocking(delay) => {
+                    let delay = sample(delay);
+                    let _ = tokio::task::spawn_blocking(move || std::thread::sleep(delay)).await;
+                }
+                Step::Alloc(bytes) => held.push(touched(*bytes)),
+            }
+        }
+        std::hint::black_box(held);
+        sum
+    }
+}
+
+fn cpu_work(iterations: u64, factor: u64) -> u64 {
+    let mut sum = 0u64;
+    for i in 0..iterations {
+        sum = sum.wrapping_add(i.wrapping_mul(i).wrapping_mul(factor));
+    }
+    sum
+}
+
+/// Allocates `bytes` and writes to every page, so they count towards RSS.
+fn touched(bytes: usize) -> Vec<u8> {
+    let mut block = vec![0u8; bytes];
+    for i in (0..bytes).step_by(4096) {
+        block[i] = 1;
+    }
+    std::hint::black_box(block)
+}
+
+impl FromStr for WorkModel {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        if spec == "none" || spec.is_empty() {
+            return Ok(Self::default());
+        }
+        let steps: Vec<Step> = spec.split('+').map(|step| parse_step(step.trim())).collect::<Result<_, _>>()?;
+        if steps.len() > MAX_STEPS {
+            return Err(format!("a model has at most {} steps", MAX_STEPS));
+        }
+        let iterations: u64 = steps.iter().map(|step| if let Step::Cpu(n) = step { *n } else { 0 }).sum();
+        if iterations > MAX_ITERATIONS {
+            return Err(format!("cpu iterations are capped at {} per model", MAX_ITERATIONS));
+        }
+        let bytes: usize = steps.iter().map(|step| if let Step::Alloc(n) = step { *n } else { 0 }).sum();
+        if bytes > MAX_ALLOC {
+            return Err("allocations are capped at 1GiB per model".to_string());
+        }
+        Ok(Self { steps })
+    }
+}
+
+fn parse_step(step: &str) -> Result<Step, String> {
+    let (kind, arg) = step
+        .split_once(':')
+        .ok_or_else(|| format!("'{}' is not a step like cpu:50000, sleep:5ms or alloc:64KiB", step))?;
+    match kind {
+        "cpu" => match arg.parse::<u64>() {
+            Ok(n) if n <= MAX_ITERATIONS => Ok(Step::Cpu(n)),
+            Ok(_) => Err(format!("cpu iterations are capped at {}", MAX_ITERATIONS)),
+            Err(_) => Err(format!("'{}' is not a number of iterations", arg)),
+        },
+        "sleep" => arg.parse().map(Step::Sleep),
+        "blocking" => arg.parse().map(Step::Blocking),
+        "alloc" => parse_bytes(arg).map(Step::Alloc),
+        _ => Err(format!("unknown step '{}' (expected cpu, sleep, blocking, alloc or none)", kind)),
+    }
+}
+
+impl FromStr for Delay {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = spec.split_once(':').unwrap_or(("fixed", spec));
+        let delay = match kind {
+            "fixed" => Delay::Fixed(parse_duration(arg)?),
+            "uniform" => {
+                let (low, high) = arg
+                    .split_once('-')
+                    .ok_or_else(|| format!("'{}' is not a range like 1ms-9ms", arg))?;
+                let (low, high) = (parse_duration(low)?, parse_duration(high)?);
+                if low > high {
+                    return Err(format!("uniform range {} is empty", arg));
+                }
+                Delay::Uniform(low, high)
+            }
+            "exp" => Delay::Exponential {
+                mean: parse_duration(arg)?,
+            },
+            "lognormal" => {
+                let (median, sigma) = arg
+                    .split_once(':')
+                    .ok_or_else(|| format!("'{}' is not a median and sigma like 5ms:0.5", arg))?;
+                match sigma.parse::<f64>() {
+                    Ok(sigma) if (0.0..=MAX_SIGMA).contains(&sigma) => Delay::LogNormal {
+                        median: parse_duration(median)?,
+                        sigma,
+                    },
+                    _ => return Err(format!("'{}' is not a sigma between 0 and {}", sigma, MAX_SIGMA)),
+                }
+            }
+            _ => return Err(format!("unknown distribution '{}' (expected fixed, uniform, exp or lognormal)", kind)),
+        };
+        Ok(delay)
+    }
+}
+
+/// Parses `250us`, `5ms` or `1.5s`, up to a minute.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(us) = s.strip_suffix("us").or_else(|| s.strip_suffix("µs")) {
+        (us, 0.000_001)
+    } else if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else {
+        return Err(format!("'{}' needs a unit: us, ms or s", s));
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 && value * scale <= MAX_DELAY.as_secs_f64() => {
+            Ok(Duration::from_secs_f64(value * scale))
+        }
+        Ok(_) => Err(format!("'{}' is not a delay between 0 and {:?}", s, MAX_DELAY)),
+        Err(_) => Err(format!("'{}' is not a duration like 250us, 5ms or 1.5s", s)),
+    }
+}
+
+/// Parses `4096`, `64KiB` or `8MiB`, up to 1 GiB.
+fn parse_bytes(s: &str) -> Result<usize, String> {
+    let (number, scale) = if let Some(kib) = s.strip_suffix("KiB") {
+        (kib, 1 << 10)
+    } else if let Some(mib) = s.strip_suffix("MiB") {
+        (mib, 1 << 20)
+    } else if let Some(gib) = s.strip_suffix("GiB") {
+        (gib, 1 << 30)
+    } else {
+        (s, 1)
+    };
+    match number.parse::<usize>().ok().and_then(|n| n.checked_mul(scale)) {
+        Some(bytes) if bytes <= MAX_ALLOC => Ok(bytes),
+        Some(_) => Err("allocations are capped at 1GiB".to_string()),
+        None => Err(format!("'{}' is not a size like 4096, 64KiB or 8MiB", s)),
+    }
+}
+
+impl fmt::Display for WorkModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.steps.is_empty() {
+            return write!(f, "none");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, "+")?;
+            }
+            match step {
+                Step::Cpu(iterations) => write!(f, "cpu:{}", iterations)?,
+                Step::Sleep(delay) => write!(f, "sleep:{}", delay)?,
+                Step::Blocking(delay) => write!(f, "blocking:{}", delay)?,
+                Step::Alloc(bytes) => write!(f, "alloc:{}", bytes)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delay::Fixed(delay) => write!(f, "{:?}", delay),
+            Delay::Uniform(low, high) => write!(f, "uniform:{:?}-{:?}", low, high),
+            Delay::Exponential { mean } => write!(f, "exp:{:?}", mean),
+            Delay::LogNormal { median, sigma } => write!(f, "lognormal:{:?}:{}", median, sigma),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_step_and_distribution() {
+        let model: WorkModel = "cpu:20000+sleep:5ms+blocking:uniform:1ms-9ms+sleep:exp:250us+sleep:lognormal:1.5s:0.8+alloc:64KiB"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            model.steps,
+            vec![
+                Step::Cpu(20_000),
+                Step::Sleep(Delay::Fixed(Duration::from_millis(5))),
+                Step::Blocking(Delay::Uniform(Duration::from_millis(1), Duration::from_millis(9))),
+                Step::Sleep(Delay::Exponential {
+                    mean: Duration::from_micros(250)
+                }),
+                Step::Sleep(Delay::LogNormal {
+                    median: Duration::from_millis(1500),
+                    sigma: 0.8
+                }),
+                Step::Alloc(64 << 10),
+            ]
+        );
+        assert_eq!("none".parse::<WorkModel>().unwrap(), WorkModel::default());
+        assert_eq!("".parse::<WorkModel>().unwrap(), WorkModel::default());
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_model() {
+        for spec in ["cpu:50000", "sleep:uniform:1ms-9ms+alloc:1MiB", "blocking:lognormal:4ms:0.5+sleep:exp:250us"] {
+            let model: WorkModel = spec.parse().unwrap();
+            assert_eq!(model.to_string().parse::<WorkModel>().unwrap(), model, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_kinds() {
+        assert!("nap:5ms".parse::<WorkModel>().unwrap_err().contains("unknown step"));
+        assert!("cpu".parse::<WorkModel>().is_err());
+        assert!("sleep:gamma:5ms".parse::<WorkModel>().unwrap_err().contains("unknown distribution"));
+        assert!("sleep:5".parse::<WorkModel>().unwrap_err().contains("needs a unit"));
+        assert!("alloc:64KB".parse::<WorkModel>().is_err());
+    }
+
+    #[test]
+    fn rejects_negative_nan_and_oversized_values() {
+        for spec in [
+            "cpu:-1",
+            "cpu:1000000001",
+            "sleep:-5ms",
+            "sleep:NaNms",
+            "sleep:infs",
+            "sleep:61s",
+            "sleep:uniform:9ms-1ms",
+            "sleep:exp:-1ms",
+            "sleep:lognormal:5ms:-0.5",
+            "sleep:lognormal:5ms:NaN",
+            "sleep:lognormal:5ms:inf",
+            "sleep:lognormal:5ms:10.5",
+            "alloc:2GiB",
+            "alloc:-1",
+        ] {
+            assert!(spec.parse::<WorkModel>().is_err(), "{} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn samples_stay_within_the_cap() {
+        let widest = Delay::LogNormal {
+            median: MAX_DELAY,
+            sigma: MAX_SIGMA,
+        };
+        let slowest = Delay::Exponential { mean: MAX_DELAY };
+        for _ in 0..10_000 {
+            assert!(widest.sample() <= MAX_DELAY);
+            assert!(slowest.sample() <= MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn sampling_never_overflows_a_duration() {
+        // Built directly, past what the parser accepts
+        let huge = Delay::LogNormal {
+            median: Duration::MAX,
+            sigma: 1e6,
+        };
+        let zero = Delay::LogNormal {
+            median: Duration::ZERO,
+            sigma: 1e6,
+        };
+        for _ in 0..1000 {
+            assert!(huge.sample() <= MAX_DELAY);
+            assert_eq!(zero.sample(), Duration::ZERO);
+        }
+        assert_eq!(scaled(Duration::from_secs(1), f64::INFINITY), MAX_DELAY);
+        assert_eq!(scaled(Duration::from_secs(1), 2.5), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn caps_the_whole_model_as_well_as_each_step() {
+        let twice = |step: &str| format!("{}+{}", step, step);
+        let error = twice("cpu:600000000").parse::<WorkModel>().unwrap_err();
+        assert!(error.contains("per model"), "{}", error);
+        let error = twice("alloc:768MiB").parse::<WorkModel>().unwrap_err();
+        assert!(error.contains("per model"), "{}", error);
+        assert!(twice("cpu:500000000").parse::<WorkModel>().is_ok());
+        assert!(twice("alloc:512MiB").parse::<WorkModel>().is_ok());
+
+        let steps = |n: usize| vec!["cpu:1"; n].join("+");
+        assert!(steps(MAX_STEPS).parse::<WorkModel>().is_ok());
+        let error = steps(MAX_STEPS + 1).parse::<WorkModel>().unwrap_err();
+        assert!(error.contains("at most 32 steps"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_share_one_budget_per_run() {
+        let model: WorkModel = "sleep:45s+sleep:45s+sleep:lognormal:60s:10".parse().unwrap();
+        let started = tokio::time::Instant::now();
+        model.run(1).await;
+        assert_eq!(started.elapsed(), MAX_DELAY);
+
+        let started = tokio::time::Instant::now();
+        "sleep:20s+sleep:30s".parse::<WorkModel>().unwrap().run(1).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(50));
+    }
+
+    #[test]
+    fn cpu_work_wraps_instead_of_overflowing() {
+        assert_eq!(cpu_work(3, 1), 5);
+        cpu_work(1000, u64::MAX);
+    }
+}